@@ -0,0 +1,11 @@
+-- Fragments indexed so far have no recorded location, and one can't be
+-- worked out without parsing their file again.  SQLite also only adds a NOT
+-- NULL column without a default to an empty table, so every file is indexed
+-- again.
+DELETE FROM file_embeddings;
+DELETE FROM file;
+
+ALTER TABLE file_embeddings ADD COLUMN start_line INTEGER NOT NULL;
+ALTER TABLE file_embeddings ADD COLUMN end_line INTEGER NOT NULL;
+ALTER TABLE file_embeddings ADD COLUMN start_byte INTEGER NOT NULL;
+ALTER TABLE file_embeddings ADD COLUMN end_byte INTEGER NOT NULL;
@@ -0,0 +1,6 @@
+CREATE TABLE embedding_cache (
+    model TEXT NOT NULL,
+    text_hash BLOB NOT NULL,
+    embedding BLOB NOT NULL,
+    PRIMARY KEY (model, text_hash)
+);
@@ -0,0 +1 @@
+ALTER TABLE file_embeddings ADD COLUMN page INTEGER;
@@ -0,0 +1,13 @@
+CREATE TABLE file_metadata (
+    file_path BLOB NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (file_path, key)
+);
+
+CREATE TRIGGER clear_file_metadata_on_hash_change
+AFTER UPDATE OF hash ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM file_metadata WHERE file_path = NEW.path;
+END;
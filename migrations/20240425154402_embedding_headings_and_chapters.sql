@@ -0,0 +1,8 @@
+-- Fragments now record the headings they're under.  PDFs have no headings,
+-- so theirs are kept as they are; other files are parsed again.
+DELETE FROM file_embeddings WHERE CAST(file_path AS TEXT) NOT LIKE '%.pdf';
+DELETE FROM file_metadata WHERE CAST(file_path AS TEXT) NOT LIKE '%.pdf';
+DELETE FROM file WHERE CAST(path AS TEXT) NOT LIKE '%.pdf';
+
+ALTER TABLE file_embeddings ADD COLUMN heading_path TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE file_embeddings ADD COLUMN chapter TEXT;
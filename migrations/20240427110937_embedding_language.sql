@@ -0,0 +1,3 @@
+ALTER TABLE file_embeddings ADD COLUMN language TEXT;
+
+CREATE INDEX file_embeddings_language ON file_embeddings (language);
@@ -0,0 +1,6 @@
+-- Notebooks were indexed as raw JSON until now.  Forget them so they are
+-- reparsed cell by cell.
+DELETE FROM file_embeddings WHERE CAST(file_path AS TEXT) LIKE '%.ipynb';
+DELETE FROM file WHERE CAST(path AS TEXT) LIKE '%.ipynb';
+
+ALTER TABLE file_embeddings ADD COLUMN cell INTEGER;
@@ -0,0 +1,7 @@
+CREATE TRIGGER clear_on_file_delete
+AFTER DELETE ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM file_embeddings WHERE file_path = OLD.path;
+ DELETE FROM file_metadata WHERE file_path = OLD.path;
+END;
@@ -0,0 +1,5 @@
+CREATE TABLE git_repository (
+    path BLOB NOT NULL,
+    last_commit TEXT NOT NULL,
+    PRIMARY KEY (path)
+);
@@ -0,0 +1,20 @@
+-- Every file now records its `source` in its metadata, and every fragment
+-- carries its file's metadata along with its own.  Neither can be filled in
+-- here: telling code from plain text takes the parser's list of languages,
+-- and an mbox message's headers are only in the mbox.  So every file, and
+-- every commit, is indexed again.
+DELETE FROM file_embeddings;
+DELETE FROM file;
+DELETE FROM git_repository;
+
+-- A JSON object of arrays: the file's metadata, overridden by the fragment's.
+ALTER TABLE file_embeddings ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+
+DROP TABLE file_metadata;
+
+CREATE TABLE file_metadata (
+    file_path BLOB NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (file_path, key, value)
+);
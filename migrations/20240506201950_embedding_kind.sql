@@ -0,0 +1,12 @@
+-- Fragments now record whether they are headings, and Org headings their
+-- tags and TODO state.  PDFs and mail have no headings, so all their
+-- fragments are paragraphs and are kept.  Everything else is parsed again,
+-- commits (headed by their summary) and Maildir messages (which can't be
+-- told apart by their path) included.
+DELETE FROM file
+WHERE CAST(path AS TEXT) NOT LIKE '%.pdf'
+  AND CAST(path AS TEXT) NOT LIKE '%.mbox'
+  AND CAST(path AS TEXT) NOT LIKE '%.eml';
+DELETE FROM git_repository;
+
+ALTER TABLE file_embeddings ADD COLUMN kind TEXT NOT NULL DEFAULT 'paragraph';
@@ -0,0 +1,44 @@
+-- Links are read from Org and Markdown syntax by the text parser, which also
+-- takes any file no other parser does, so those files are parsed again.
+-- PDFs, EPUBs, HTML, notebooks, mail and commits have no links read from
+-- them and are kept.  Source code can't be told apart without the parser's
+-- list of languages, so it's parsed again too.
+DELETE FROM file
+WHERE CAST(path AS TEXT) NOT LIKE '%.pdf'
+  AND CAST(path AS TEXT) NOT LIKE '%.epub'
+  AND CAST(path AS TEXT) NOT LIKE '%.html'
+  AND CAST(path AS TEXT) NOT LIKE '%.htm'
+  AND CAST(path AS TEXT) NOT LIKE '%.xhtml'
+  AND CAST(path AS TEXT) NOT LIKE '%.ipynb'
+  AND CAST(path AS TEXT) NOT LIKE '%.mbox'
+  AND CAST(path AS TEXT) NOT LIKE '%.eml'
+  AND NOT EXISTS (
+    SELECT 1 FROM git_repository
+    WHERE CAST(file.path AS TEXT) LIKE CAST(git_repository.path AS TEXT) || '@%'
+  );
+
+-- A link from a fragment of one file to another file or an Org ID.
+CREATE TABLE file_link (
+    source_path BLOB NOT NULL,
+    source_line INTEGER NOT NULL,
+    target_path BLOB,
+    target_id TEXT,
+    description TEXT
+);
+
+CREATE INDEX file_link_source_path ON file_link (source_path);
+CREATE INDEX file_link_target_path ON file_link (target_path);
+
+CREATE TRIGGER clear_links_on_hash_change
+AFTER UPDATE OF hash ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM file_link WHERE source_path = NEW.path;
+END;
+
+CREATE TRIGGER clear_links_on_file_delete
+AFTER DELETE ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM file_link WHERE source_path = OLD.path;
+END;
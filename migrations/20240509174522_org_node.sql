@@ -0,0 +1,44 @@
+-- Org IDs are read from property drawers, which only the text parser
+-- looks for.  It takes Org files and anything no other parser does, so only
+-- those can hold IDs.  Files with a parser of their own, and commits, are
+-- kept; source code is parsed again, not being told apart from text here.
+DELETE FROM file
+WHERE CAST(path AS TEXT) NOT LIKE '%.pdf'
+  AND CAST(path AS TEXT) NOT LIKE '%.epub'
+  AND CAST(path AS TEXT) NOT LIKE '%.html'
+  AND CAST(path AS TEXT) NOT LIKE '%.htm'
+  AND CAST(path AS TEXT) NOT LIKE '%.xhtml'
+  AND CAST(path AS TEXT) NOT LIKE '%.ipynb'
+  AND CAST(path AS TEXT) NOT LIKE '%.mbox'
+  AND CAST(path AS TEXT) NOT LIKE '%.eml'
+  AND NOT EXISTS (
+    SELECT 1 FROM git_repository
+    WHERE CAST(file.path AS TEXT) LIKE CAST(git_repository.path AS TEXT) || '@%'
+  );
+
+-- A heading or file with an `:ID:` property.  IDs are meant to be unique,
+-- but a copied file will repeat them, so they aren't a key.
+CREATE TABLE org_node (
+    id TEXT NOT NULL,
+    file_path BLOB NOT NULL,
+    line INTEGER,
+    title TEXT
+);
+
+CREATE INDEX org_node_id ON org_node (id);
+CREATE INDEX org_node_file_path ON org_node (file_path);
+CREATE INDEX file_link_target_id ON file_link (target_id);
+
+CREATE TRIGGER clear_nodes_on_hash_change
+AFTER UPDATE OF hash ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM org_node WHERE file_path = NEW.path;
+END;
+
+CREATE TRIGGER clear_nodes_on_file_delete
+AFTER DELETE ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM org_node WHERE file_path = OLD.path;
+END;
@@ -0,0 +1,11 @@
+-- The model the vectors in file_embeddings came from, and their length,
+-- which vss_file_embeddings is built for.  There is only ever one row.
+CREATE TABLE embedding_index (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    model TEXT NOT NULL,
+    dimension INTEGER NOT NULL
+);
+
+-- Everything indexed so far came from the one model there was.
+INSERT INTO embedding_index (id, model, dimension)
+VALUES (1, 'BAAI/bge-small-en-v1.5', 384);
@@ -0,0 +1,19 @@
+-- Each rebuild of the vector index goes into a new vss table, so the old one
+-- can be searched until the new one is swapped in.  Generation 0 is
+-- vss_file_embeddings, later ones vss_file_embeddings_<generation>.
+ALTER TABLE embedding_index ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+
+-- The model a rebuild in progress is embedding with.  There is at most one.
+CREATE TABLE reembedding (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    model TEXT NOT NULL,
+    dimension INTEGER NOT NULL,
+    generation INTEGER NOT NULL
+);
+
+-- The new vectors of the fragments re-embedded so far, by the rowid of their
+-- file_embeddings row.  Written back to file_embeddings when swapping in.
+CREATE TABLE reembedding_vector (
+    file_embedding_id INTEGER PRIMARY KEY,
+    embedding BLOB NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Files whose fragments could not be embedded, to be indexed again until
+-- they are.
+CREATE TABLE index_retry (
+    path BLOB PRIMARY KEY,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    error TEXT NOT NULL,
+    failed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- A commit's touched paths were stored as a single newline-joined value,
+-- which a `paths` filter never matches.  They are now one value each, so
+-- commits are forgotten, along with how far each repository was indexed,
+-- to be read again.
+DELETE FROM file
+WHERE EXISTS (
+    SELECT 1 FROM git_repository
+    WHERE CAST(file.path AS TEXT) LIKE CAST(git_repository.path AS TEXT) || '@%'
+);
+
+DELETE FROM git_repository;
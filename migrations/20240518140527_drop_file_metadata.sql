@@ -0,0 +1,12 @@
+-- Every fragment carries its file's metadata, which is what searches filter
+-- on, so the per-file copy was written but never read.
+DROP TRIGGER clear_file_metadata_on_hash_change;
+DROP TRIGGER clear_on_file_delete;
+DROP TABLE file_metadata;
+
+CREATE TRIGGER clear_on_file_delete
+AFTER DELETE ON file
+FOR EACH ROW
+BEGIN
+ DELETE FROM file_embeddings WHERE file_path = OLD.path;
+END;
@@ -0,0 +1,4 @@
+-- Notebook outputs were located by their own lines, which overlap the
+-- source's.  They now point at the source of their cell, so notebooks are
+-- indexed again.
+DELETE FROM file WHERE CAST(path AS TEXT) LIKE '%.ipynb';
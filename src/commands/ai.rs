@@ -33,7 +33,7 @@ You are a helpful assistant.<|eot_id|>
 <|start_header_id|>user<|end_header_id|>
 
 Who was president of the US in 1978?<|eot_id|>
-"#
+"#,
                 )?;
 
                 while let Some(Ok(item)) = stream.next().await {
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use async_trait::async_trait;
 use clap::{Args, Parser, Subcommand};
 
-use crate::services::embeddings::EmbeddingsService;
+use crate::{config::Config, services::parsers};
 
 use super::Executor;
 
@@ -29,12 +29,16 @@ pub struct Embeddings {
 #[async_trait]
 impl Executor for Embeddings {
     async fn execute(&self) -> anyhow::Result<()> {
-        let svc = EmbeddingsService::try_new()?;
         match self.command {
             Commands::Parse(ref args) => {
-                let tokens = svc.parse_file(&args.file).await?;
+                let config = Config::load().await?;
+                let parsed = parsers::parse_file(&args.file, &config.parse_options()).await?;
 
-                for token in tokens {
+                for (key, value) in parsed.metadata.iter() {
+                    println!("{key}: {value}");
+                }
+
+                for token in parsed.fragments {
                     println!("{:?}", token);
                 }
 
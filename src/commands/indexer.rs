@@ -1,16 +1,26 @@
 use std::path::PathBuf;
 
+use ansi_term::Style;
 use async_trait::async_trait;
 use clap::{Args, Parser, Subcommand};
 use futures::StreamExt;
+use tokio::time::{interval_at, Duration, Instant};
 
 use crate::{
+    config::Config,
     context::Context,
-    services::{files::FilesService, indexer::IndexerService},
+    entity::types::{file_embedding::FileEmbedding, index_retry::IndexRetry},
+    services::{
+        embeddings::EmbeddingsService, files::FilesService, git::GitService,
+        indexer::IndexerService,
+    },
 };
 
 use super::Executor;
 
+/// How often files that failed to index are tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Args, Debug)]
 struct RunArgs {
     #[arg(env, long)]
@@ -36,6 +46,21 @@ enum Commands {
     /// Finds all files of type and prints them to console.
     /// For debugging.
     FindFiles(FindFilesArgs),
+
+    /// Lists the open TODO items across all indexed Org files, soonest
+    /// deadline or scheduled date first.
+    Agenda,
+
+    /// Lists the files that failed to index and are waiting to be retried,
+    /// with why they last failed.
+    Failures,
+
+    /// Re-embeds every indexed fragment with the configured
+    /// `embedding_model`, without reading the files again.  Search keeps
+    /// using the old embeddings until all are done.  A running indexer
+    /// stops once they're swapped in, and has to be restarted to embed with
+    /// the new model.
+    Reembed,
 }
 
 #[derive(Parser, Debug)]
@@ -50,38 +75,213 @@ impl Executor for Indexer {
         let context = Context::default();
         match self.command {
             Commands::Run(ref args) => {
-                let files_svc = FilesService::new(args.watch_path.to_path_buf());
-                let files = files_svc
+                let config = Config::load().await?;
+
+                // Searches scope by absolute paths, so files are indexed
+                // under theirs.
+                let watch_path = args.watch_path.canonicalize()?;
+                let files_svc = FilesService::new(watch_path.to_path_buf());
+                let mut files = files_svc
                     .read_tree()
                     .await?
                     .into_iter()
                     .map(|x| x.path())
                     .collect::<Vec<_>>();
 
+                for mailbox in &config.mailboxes {
+                    files.extend(FilesService::read_mailbox(mailbox).await?);
+                }
+
+                // While `indexer reembed` runs, new fragments are embedded
+                // like the old ones, and re-embedded by it.
+                let embeddings = EmbeddingsService::for_index(&context, &config).await?;
+
                 // Always do a full reindexing on startup.
-                let indexer_svc = IndexerService::try_new(context, args.watch_path.to_path_buf())?;
+                let indexer_svc = IndexerService::new(
+                    context,
+                    watch_path.to_path_buf(),
+                    config.parse_options(),
+                    embeddings,
+                );
+                indexer_svc.ensure_embedding_index().await?;
+
+                // Files that fail are queued, and retried below.
                 println!("Reindexing changed files");
-                indexer_svc.index_files(&files).await?;
+                if let Err(error) = indexer_svc.index_files(&files).await {
+                    println!("Could not index some files: {:#}", error);
+                }
+
+                // Commits are made through the git directory, so watching it
+                // is enough to pick up new ones.
+                let mut git_dirs = vec![];
+                for repository in &config.repositories {
+                    println!("Indexing new commits in {}", repository.display());
+                    if let Err(error) = indexer_svc.index_repository(repository).await {
+                        println!("Could not index {}: {:#}", repository.display(), error);
+                    }
+
+                    match GitService::new(repository.to_path_buf()).git_dir() {
+                        Ok(git_dir) => git_dirs.push((git_dir, repository.to_path_buf())),
+                        Err(error) => {
+                            println!("Not watching {}: {:#}", repository.display(), error)
+                        }
+                    }
+                }
                 println!("OK, inserted...");
 
-                let (_debouncer, mut rx) = files_svc.watch(&args.watch_path)?;
+                let mut watch_paths = vec![watch_path.to_path_buf()];
+                watch_paths.extend(config.mailboxes.iter().cloned());
+                watch_paths.extend(git_dirs.iter().map(|(git_dir, _)| git_dir.to_path_buf()));
+
+                let mut retries = interval_at(Instant::now() + RETRY_INTERVAL, RETRY_INTERVAL);
+
+                let (_debouncer, mut rx) = files_svc.watch(&watch_paths)?;
                 loop {
-                    while let Some(res) = rx.next().await {
-                        if let Ok(events) = res {
-                            let files = events
+                    tokio::select! {
+                        _ = retries.tick() => {
+                            indexer_svc.check_embedding_model().await?;
+                            if let Err(error) = indexer_svc.retry_failed().await {
+                                println!("Could not index some files: {:#}", error);
+                            }
+
+                            // Commits that failed are picked up again from
+                            // the last commit indexed.
+                            for (_, repository) in &git_dirs {
+                                let result = indexer_svc.index_repository(repository).await;
+                                if let Err(error) = result {
+                                    println!(
+                                        "Could not index {}: {:#}",
+                                        repository.display(),
+                                        error
+                                    );
+                                }
+                            }
+                        }
+
+                        Some(Ok(events)) = rx.next() => {
+                            // Vectors from the old model don't belong in an
+                            // index `indexer reembed` has swapped out.
+                            indexer_svc.check_embedding_model().await?;
+
+                            let (git_events, events): (Vec<_>, Vec<_>) =
+                                events.into_iter().partition(|x| {
+                                    git_dirs
+                                        .iter()
+                                        .any(|(git_dir, _)| x.path.starts_with(git_dir))
+                                });
+
+                            for (git_dir, repository) in &git_dirs {
+                                if git_events.iter().any(|x| x.path.starts_with(git_dir)) {
+                                    let result = indexer_svc.index_repository(repository).await;
+                                    if let Err(error) = result {
+                                        println!(
+                                            "Could not index {}: {:#}",
+                                            repository.display(),
+                                            error
+                                        );
+                                    }
+                                }
+                            }
+
+                            // Maildir clients move messages between `new/`
+                            // and `cur/` and rename them to change flags, so
+                            // paths that have gone away are forgotten.
+                            let (files, removed): (Vec<_>, Vec<_>) = events
                                 .into_iter()
-                                .filter_map(|x| if x.path.is_file() { Some(x.path) } else { None })
-                                .collect::<Vec<_>>();
+                                .map(|x| x.path)
+                                .filter(|x| !x.is_dir())
+                                .partition(|x| x.is_file());
+
+                            if !removed.is_empty() {
+                                if let Err(error) = indexer_svc.remove_files(&removed[..]).await {
+                                    println!("Could not remove some files: {:#}", error);
+                                }
+                            }
 
                             if files.is_empty() {
                                 continue;
                             }
 
                             println!("Updating {:?}", files);
-                            let _ = indexer_svc.index_files(&files[..]).await;
+                            if let Err(error) = indexer_svc.index_files(&files[..]).await {
+                                println!("Could not index some files: {:#}", error);
+                            }
+                        }
+                    }
+                }
+            }
+
+            Commands::Failures => {
+                let retries = IndexRetry::find_all(&context).await?;
+
+                for retry in retries {
+                    println!(
+                        "{} {}",
+                        Style::new()
+                            .bold()
+                            .paint(retry.path.0.display().to_string()),
+                        Style::new().dimmed().paint(format!(
+                            "({} attempts, last at {})",
+                            retry.attempts, retry.failed_at
+                        ))
+                    );
+                    println!("  {}", retry.error);
+                }
+
+                Ok(())
+            }
+
+            Commands::Reembed => {
+                let config = Config::load().await?;
+
+                // Fragments are re-embedded from the contents stored with
+                // them, so no files are read.
+                let indexer_svc = IndexerService::new(
+                    context,
+                    PathBuf::new(),
+                    config.parse_options(),
+                    EmbeddingsService::from_config(&config).await?,
+                );
+                indexer_svc.reembed().await?;
+
+                Ok(())
+            }
+
+            Commands::Agenda => {
+                let items = FileEmbedding::find_open_items(&context).await?;
+
+                for item in items {
+                    let first = |key: &str| item.metadata.get(key).and_then(|x| x.first());
+                    let title = item.heading_path.last().cloned().unwrap_or_default();
+
+                    let mut line = format!(
+                        "{} {}",
+                        Style::new()
+                            .bold()
+                            .paint(first("todo").cloned().unwrap_or_default()),
+                        title
+                    );
+                    if let Some(tags) = item.metadata.get("tag").filter(|x| !x.is_empty()) {
+                        line.push_str(&format!("  :{}:", tags.join(":")));
+                    }
+                    for key in ["deadline", "scheduled"] {
+                        if let Some(date) = first(key) {
+                            line.push_str(&format!("  {}: {}", key, date));
                         }
                     }
+
+                    println!("{}", line);
+                    println!(
+                        "  {}",
+                        Style::new().italic().paint(format!(
+                            "{}:{}",
+                            item.file_path.0.display(),
+                            item.start_line
+                        ))
+                    );
                 }
+
+                Ok(())
             }
 
             Commands::FindFiles(ref args) => {
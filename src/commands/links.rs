@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use ansi_term::{Colour, Style};
+use async_trait::async_trait;
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    context::Context,
+    entity::{
+        columns::FilePath,
+        types::{file::File, file_link::FileLink, org_node::OrgNode},
+        Entity,
+    },
+};
+
+use super::{canonicalize, Executor};
+
+#[derive(Args, Debug)]
+struct FileArgs {
+    #[arg(long)]
+    /// The indexed file to list links for.
+    file: PathBuf,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct BacklinksArgs {
+    #[arg(long)]
+    /// The indexed file to list links to, including links to its Org nodes.
+    file: Option<PathBuf>,
+
+    #[arg(long)]
+    /// The ID of the Org node to list links to.
+    id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct BrokenArgs {
+    #[arg(long)]
+    /// Only list the broken links in this file.
+    file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Lists the links a file makes to other files and notes.
+    Outgoing(FileArgs),
+
+    /// Lists the links other files make to a file or Org node.
+    Backlinks(BacklinksArgs),
+
+    /// Lists links to files that don't exist and to unknown Org IDs.
+    Broken(BrokenArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct Links {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// Prints where a link is made from and what it points at, followed by the
+/// location of the Org node it resolves to, or a note on the target's state.
+fn print_link(link: &FileLink, node: Option<&OrgNode>, note: Option<&str>) {
+    let source = format!("{}:{}", link.source_path.0.display(), link.source_line);
+    let target = match (&link.target_path, &link.target_id) {
+        (Some(path), _) => path.0.display().to_string(),
+        (None, Some(id)) => format!("id:{}", id),
+        (None, None) => String::new(),
+    };
+
+    let mut line = format!("{} -> {}", Style::new().bold().paint(source), target);
+    if let Some(description) = &link.description {
+        line.push_str(&format!(" {}", Style::new().italic().paint(description)));
+    }
+    if let Some(node) = node {
+        let location = match node.line {
+            Some(node_line) => format!("{}:{}", node.file_path.0.display(), node_line),
+            None => node.file_path.0.display().to_string(),
+        };
+        match &node.title {
+            Some(title) => line.push_str(&format!(" ({}: {})", location, title)),
+            None => line.push_str(&format!(" ({})", location)),
+        }
+    }
+    if let Some(note) = note {
+        line.push_str(&format!(" {}", Colour::Red.paint(format!("({})", note))));
+    }
+
+    println!("{}", line);
+}
+
+#[async_trait]
+impl Executor for Links {
+    async fn execute(&self) -> anyhow::Result<()> {
+        let context = Context::default();
+        match self.command {
+            Commands::Outgoing(ref args) => {
+                let file = canonicalize(&args.file)?;
+                let links = FileLink::find_many(&context, &[FilePath::new(file)]).await?;
+
+                let targets = links
+                    .iter()
+                    .filter_map(|x| x.target_path.clone())
+                    .collect::<Vec<_>>();
+                let indexed = File::find_many(&context, &targets)
+                    .await?
+                    .into_iter()
+                    .map(|x| x.path.0)
+                    .collect::<HashSet<_>>();
+
+                let ids = links
+                    .iter()
+                    .filter_map(|x| x.target_id.clone())
+                    .collect::<Vec<_>>();
+                let nodes = OrgNode::find_many(&context, &ids)
+                    .await?
+                    .into_iter()
+                    .map(|x| (x.id.to_string(), x))
+                    .collect::<HashMap<_, _>>();
+
+                for link in links {
+                    let node = link.target_id.as_ref().and_then(|x| nodes.get(x));
+                    let note = match (&link.target_path, &link.target_id) {
+                        (Some(path), _) if indexed.contains(&path.0) => None,
+                        (Some(path), _) if path.0.exists() => Some("not indexed"),
+                        (Some(_), _) => Some("missing"),
+                        (None, Some(_)) if node.is_none() => Some("unknown ID"),
+                        _ => None,
+                    };
+                    print_link(&link, node, note);
+                }
+
+                Ok(())
+            }
+
+            Commands::Backlinks(ref args) => {
+                let links = match (&args.file, &args.id) {
+                    (Some(file), _) => {
+                        FileLink::find_backlinks(&context, canonicalize(file)?).await?
+                    }
+                    (None, Some(id)) => {
+                        FileLink::find_backlinks_to_id(&context, id.to_string()).await?
+                    }
+                    (None, None) => vec![],
+                };
+
+                for link in links {
+                    print_link(&link, None, None);
+                }
+
+                Ok(())
+            }
+
+            Commands::Broken(ref args) => {
+                // Links to files that exist but aren't indexed, like images,
+                // aren't broken.
+                let file = args.file.as_deref().map(canonicalize).transpose()?;
+                let links = FileLink::find_unresolved(&context, file).await?;
+                for link in links {
+                    match &link.target_path {
+                        Some(path) if path.0.exists() => {}
+                        Some(_) => print_link(&link, None, Some("missing")),
+                        None => print_link(&link, None, Some("unknown ID")),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,11 +1,22 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
 use async_trait::async_trait;
 
 pub mod ai;
 pub mod embeddings;
 pub mod indexer;
+pub mod links;
 pub mod search;
 
 #[async_trait]
 pub trait Executor {
     async fn execute(&self) -> anyhow::Result<()>;
 }
+
+/// Makes a path given on the command line absolute, with symlinks resolved,
+/// like the paths files are indexed under.
+fn canonicalize(path: &Path) -> anyhow::Result<PathBuf> {
+    path.canonicalize()
+        .map_err(|error| anyhow!("Could not find {}: {}", path.display(), error))
+}
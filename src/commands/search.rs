@@ -1,33 +1,361 @@
+use std::{convert::Infallible, path::PathBuf, str::FromStr};
+
 use ansi_term::Style;
+use anyhow::bail;
 use async_trait::async_trait;
-use clap::Args;
+use clap::{value_parser, ArgGroup, Args};
+
+use crate::{
+    config::Config,
+    context::Context,
+    entity::types::{
+        embedding_index::EmbeddingIndex,
+        file_embedding::{
+            FileEmbedding, FileGrouping, FileMatches, FileScore, FilterOperator, MetadataFilter,
+            SearchFilters, SEARCH_LIMIT,
+        },
+    },
+    services::{
+        embeddings::EmbeddingsService,
+        reranker::{RerankerService, DEFAULT_RERANKER_MODEL},
+    },
+};
+
+use super::{canonicalize, Executor};
+
+/// How many of the nearest fragments are re-ranked, unless configured.
+const DEFAULT_RERANK_CANDIDATES: u64 = 20;
 
-use crate::{context::Context, entity::types::file_embedding::FileEmbedding};
+/// A file, or a line in one, to find notes like.  Written `path` or
+/// `path:line`.
+#[derive(Debug, Clone)]
+struct LikeTarget {
+    path: PathBuf,
+    line: Option<usize>,
+}
+
+impl FromStr for LikeTarget {
+    type Err = Infallible;
 
-use super::Executor;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(':') {
+            Some((path, line)) if line.parse::<usize>().is_ok() => Ok(LikeTarget {
+                path: PathBuf::from(path),
+                line: line.parse().ok(),
+            }),
+            _ => Ok(LikeTarget {
+                path: PathBuf::from(s),
+                line: None,
+            }),
+        }
+    }
+}
 
 #[derive(Args, Debug)]
+#[command(group(ArgGroup::new("grouping").args(["by_file", "like"]).multiple(true)))]
 pub struct Search {
+    #[arg(long, required_unless_present = "like", conflicts_with = "like")]
+    query: Option<String>,
+
+    #[arg(long, conflicts_with_all = ["diversity", "rerank"])]
+    /// Find the files most like this one, given as `path`, or like the
+    /// fragment at a line of it, given as `path:line`.  The file itself is
+    /// left out.
+    like: Option<LikeTarget>,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Only search source code in these languages, e.g. `rust,python`.
+    language: Vec<String>,
+
+    #[arg(long)]
+    /// Only search source code.
+    code: bool,
+
+    #[arg(long)]
+    /// Only search fragments whose metadata matches, e.g. `tag=work` or
+    /// `date>=2024-01-01`.  May be given several times.
+    filter: Vec<MetadataFilter>,
+
     #[arg(long)]
-    query: String,
+    /// Only search fragments tagged with this Org tag, directly or through a
+    /// parent heading or `#+FILETAGS`.  May be given several times.
+    tag: Vec<String>,
+
+    #[arg(long)]
+    /// Only search inside the Org node with this ID, including any headings
+    /// under it that have no ID of their own.
+    node: Option<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Only search under headings with one of these TODO keywords, e.g.
+    /// `TODO,NEXT`.
+    todo: Vec<String>,
+
+    #[arg(long)]
+    /// Only search files under this path.  May be given several times.
+    path: Vec<PathBuf>,
+
+    #[arg(long)]
+    /// Only search files matching this glob, e.g. `**/journal/*.org`.  May be
+    /// given several times.
+    glob: Vec<String>,
+
+    #[arg(long, conflicts_with = "by_file")]
+    /// Re-rank results to cover more aspects of the query, from 0 (by
+    /// relevance alone) to 1 (by how different each is from those above it).
+    /// Around 0.3 weeds out near-duplicates.
+    diversity: Option<f32>,
+
+    #[arg(long, conflicts_with_all = ["by_file", "diversity"])]
+    /// Re-rank the nearest fragments with a cross-encoder, which orders them
+    /// better but is slower.  Can be turned on for every search with
+    /// `rerank = true` in `.indexer.toml`.  The cross-encoder orders by
+    /// relevance alone, so it doesn't go with `--diversity`.
+    rerank: bool,
+
+    #[arg(long)]
+    /// Group results by file, so that one long file can't take up every
+    /// result.  Always on with `--like`.
+    by_file: bool,
+
+    #[arg(
+        long,
+        default_value_t = 3,
+        requires = "grouping",
+        value_parser = value_parser!(u64).range(1..)
+    )]
+    /// The number of distinct files to show when grouping by file.
+    files: u64,
+
+    #[arg(
+        long,
+        default_value_t = 2,
+        requires = "grouping",
+        value_parser = value_parser!(u64).range(1..)
+    )]
+    /// The number of fragments to show for each file when grouping by file.
+    snippets: u64,
+
+    #[arg(long, default_value = "best", requires = "grouping")]
+    /// How files are ranked when grouping by file: by their `best` fragment,
+    /// or by the `mean` of the fragments shown.
+    file_score: FileScore,
 }
 
 #[async_trait]
 impl Executor for Search {
     async fn execute(&self) -> anyhow::Result<()> {
         let context = Context::default();
-        let results = FileEmbedding::search(&context, &self.query).await?;
+
+        let paths = self
+            .path
+            .iter()
+            .map(|x| canonicalize(x))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut filters = SearchFilters::builder()
+            .languages(self.language.clone())
+            .code_only(self.code)
+            .metadata(
+                self.filter
+                    .iter()
+                    .cloned()
+                    .chain(self.tag.iter().map(|x| MetadataFilter {
+                        key: "tag".to_string(),
+                        operator: FilterOperator::Eq,
+                        value: x.to_string(),
+                    }))
+                    .chain(self.node.iter().map(|x| MetadataFilter {
+                        key: "node_id".to_string(),
+                        operator: FilterOperator::Eq,
+                        value: x.to_string(),
+                    }))
+                    .collect(),
+            )
+            .todo_keywords(self.todo.clone())
+            .paths(paths)
+            .globs(self.glob.clone())
+            .build();
+
+        let grouping = FileGrouping::builder()
+            .files(self.files)
+            .snippets(self.snippets)
+            .score(self.file_score)
+            .build();
+
+        if let Some(like) = &self.like {
+            let path = canonicalize(&like.path)?;
+            let embedding =
+                FileEmbedding::find_embedding_at(&context, path.to_path_buf(), like.line).await?;
+            filters.excluded_paths.push(path);
+
+            let files =
+                FileEmbedding::search_files(&context, &embedding, &filters, &grouping).await?;
+            print_files(files);
+            return Ok(());
+        }
+
+        let query = self.query.as_deref().unwrap_or_default();
+
+        // The query has to be embedded by the model the index was built
+        // with.
+        let config = Config::load().await?;
+        let embeddings = EmbeddingsService::for_index(&context, &config).await?;
+        EmbeddingIndex::check(&context, embeddings.model_id(), embeddings.dimension()).await?;
+
+        if self.by_file {
+            let embedding = embeddings.query_embedding(query).await?;
+            let files =
+                FileEmbedding::search_files(&context, &embedding, &filters, &grouping).await?;
+            print_files(files);
+            return Ok(());
+        }
+
+        if self.diversity.is_some_and(|x| !(0.0..=1.0).contains(&x)) {
+            bail!("--diversity must be between 0 and 1");
+        }
+
+        // An explicit `--diversity` wins over re-ranking turned on in the
+        // config.
+        if !(self.rerank || (config.rerank && self.diversity.is_none())) {
+            let results = FileEmbedding::search(
+                &context,
+                &embeddings,
+                query,
+                &filters,
+                SEARCH_LIMIT,
+                self.diversity,
+            )
+            .await?;
+            for result in results {
+                print_result(&result);
+            }
+            return Ok(());
+        }
+
+        let candidates = FileEmbedding::search(
+            &context,
+            &embeddings,
+            query,
+            &filters,
+            config
+                .rerank_candidates
+                .unwrap_or(DEFAULT_RERANK_CANDIDATES),
+            None,
+        )
+        .await?;
+
+        // Loading the cross-encoder reads, or downloads, the model, and
+        // running it is as heavy as embedding, so neither should hold up the
+        // runtime.
+        let model = config
+            .reranker_model
+            .unwrap_or_else(|| DEFAULT_RERANKER_MODEL.to_string());
+        let query = query.to_string();
+        let documents = candidates
+            .iter()
+            .map(|x| x.contents.to_string())
+            .collect::<Vec<_>>();
+        let order = tokio::task::spawn_blocking(move || {
+            RerankerService::try_new(&model)?.rerank(&query, &documents)
+        })
+        .await??;
+
+        let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+        let results = order
+            .into_iter()
+            .filter_map(|x| candidates.get_mut(x).and_then(|x| x.take()))
+            .take(SEARCH_LIMIT as usize);
 
         for result in results {
-            println!(
-                "{}",
-                Style::new()
-                    .bold()
-                    .paint(result.file_path.0.display().to_string())
-            );
-            println!("{}", result.contents);
+            print_result(&result);
         }
 
         Ok(())
     }
 }
+
+/// Prints each file under a heading of its own, followed by its fragments.
+fn print_files(files: Vec<FileMatches>) {
+    for file in files {
+        println!(
+            "{}",
+            Style::new()
+                .bold()
+                .underline()
+                .paint(file.file_path.display().to_string())
+        );
+        for result in file.fragments {
+            print_result(&result);
+        }
+        println!();
+    }
+}
+
+/// Prints where a fragment is, the headings it is under, and its contents.
+fn print_result(result: &FileEmbedding) {
+    let path = result.file_path.0.display();
+    let lines = match result.end_line > result.start_line {
+        true => format!("{}-{}", result.start_line, result.end_line),
+        false => result.start_line.to_string(),
+    };
+    let location = match (result.page, &result.chapter, result.cell) {
+        (Some(page), _, _) => format!("{}#page={}", path, page),
+        (None, Some(chapter), _) => format!("{}#{}:{}", path, chapter, lines),
+        (None, None, Some(cell)) => format!("{}#cell={}:{}", path, cell, lines),
+        (None, None, None) => format!("{}:{}", path, lines),
+    };
+
+    // Notes in an Org node are also known by the node's ID, and code is
+    // labelled with its language.
+    let labels = result
+        .metadata
+        .get("node_id")
+        .and_then(|x| x.first())
+        .map(|x| format!("id:{}", x))
+        .into_iter()
+        .chain(result.language.clone())
+        .collect::<Vec<_>>();
+    match labels.is_empty() {
+        true => println!("{}", Style::new().bold().paint(location)),
+        false => println!(
+            "{} {}",
+            Style::new().bold().paint(location),
+            Style::new().dimmed().paint(labels.join(" "))
+        ),
+    }
+    if !result.heading_path.is_empty() {
+        println!(
+            "{}",
+            Style::new().italic().paint(result.heading_path.join(" > "))
+        );
+    }
+    println!("{}", result.contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_target_parses_an_optional_line() {
+        let target = "notes/todo.org".parse::<LikeTarget>().unwrap();
+        assert_eq!(target.path, PathBuf::from("notes/todo.org"));
+        assert_eq!(target.line, None);
+
+        let target = "notes/todo.org:12".parse::<LikeTarget>().unwrap();
+        assert_eq!(target.path, PathBuf::from("notes/todo.org"));
+        assert_eq!(target.line, Some(12));
+    }
+
+    #[test]
+    fn like_target_keeps_colons_that_are_not_lines() {
+        let target = "notes/a:b.org".parse::<LikeTarget>().unwrap();
+        assert_eq!(target.path, PathBuf::from("notes/a:b.org"));
+        assert_eq!(target.line, None);
+
+        let target = "notes/a:b.org:3".parse::<LikeTarget>().unwrap();
+        assert_eq!(target.path, PathBuf::from("notes/a:b.org"));
+        assert_eq!(target.line, Some(3));
+    }
+}
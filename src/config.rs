@@ -1,23 +1,130 @@
-use crate::platform::home_dir;
+use crate::{platform::home_dir, services::parsers::ParseOptions};
+use anyhow::anyhow;
 use serde::Deserialize;
+use std::{io::ErrorKind, path::PathBuf};
+
+/// How a model's outputs for each token are pooled into one vector.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// The output for the first, CLS, token.
+    Cls,
+
+    /// The mean of the outputs for every token.
+    Mean,
+}
+
+/// An embedding model kept in a local directory rather than downloaded, such
+/// as one fine-tuned on your own notes.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LocalEmbeddingModel {
+    /// The directory the model was exported to ONNX in.
+    pub path: PathBuf,
+
+    /// Put before search queries, for models trained with one.
+    #[serde(default)]
+    pub query_prefix: String,
+
+    /// Put before the fragments searched, for models trained with one.
+    #[serde(default)]
+    pub passage_prefix: String,
+
+    /// How the model pools its token outputs, `cls` or `mean`.  Read from
+    /// the model's `1_Pooling/config.json` if not set.
+    pub pooling: Option<Pooling>,
+
+    /// Whether the model's vectors are normalized to unit length.  Defaults
+    /// to true.
+    pub normalize: Option<bool>,
+}
+
+/// An embedding server to embed with rather than embedding in-process, such
+/// as an Ollama or text-embeddings-inference instance.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HttpEmbeddingModel {
+    /// The server's OpenAI-style endpoint, e.g.
+    /// `http://localhost:11434/v1/embeddings`.
+    pub url: String,
+
+    /// The model the server should embed with, e.g. `nomic-embed-text`.
+    pub model: String,
+
+    /// Sent as a bearer token, for servers that want one.
+    pub api_key: Option<String>,
+
+    /// Put before search queries, for models trained with one.
+    #[serde(default)]
+    pub query_prefix: String,
+
+    /// Put before the fragments searched, for models trained with one.
+    #[serde(default)]
+    pub passage_prefix: String,
+}
 
 #[derive(Default, Deserialize, Debug)]
+#[serde(default)]
 pub struct Config {
     pub huggingface_token: Option<String>,
+
+    /// Whether to embed the text output of notebook code cells.  Changing it
+    /// reindexes notebooks on the next run.
+    pub notebook_outputs: bool,
+
+    /// Mail archives to index and watch alongside the indexed path: mbox
+    /// files, or directories holding Maildirs.
+    pub mailboxes: Vec<PathBuf>,
+
+    /// Local git repositories whose commit history to index.
+    pub repositories: Vec<PathBuf>,
+
+    /// The model to embed with, by its Hugging Face name.  Defaults to
+    /// `BAAI/bge-small-en-v1.5`.  An index holds vectors from one model only,
+    /// so changing it means indexing again.
+    pub embedding_model: Option<String>,
+
+    /// A model to embed with from a local directory, used instead of
+    /// `embedding_model`.
+    pub local_embedding_model: Option<LocalEmbeddingModel>,
+
+    /// An embedding server to embed with, used instead of `embedding_model`.
+    pub http_embedding_model: Option<HttpEmbeddingModel>,
+
+    /// Whether to always re-rank search results with a cross-encoder, as
+    /// `search --rerank` does.  Searches grouped by file, or asking for
+    /// `--diversity`, aren't re-ranked.
+    pub rerank: bool,
+
+    /// The cross-encoder to re-rank with, by its Hugging Face name.  Defaults
+    /// to `BAAI/bge-reranker-base`.
+    pub reranker_model: Option<String>,
+
+    /// How many of the nearest fragments the cross-encoder scores.  Defaults
+    /// to 20.
+    pub rerank_candidates: Option<u64>,
 }
 
 impl Config {
-    pub async fn load() -> Self {
-        let path = home_dir().join(".indexer.toml");
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            notebook_outputs: self.notebook_outputs,
+        }
+    }
 
-        if let Ok(file_contents) = tokio::fs::read_to_string(path).await {
-           let config = toml::from_str(&file_contents);
+    /// Reads `~/.indexer.toml`, or the defaults if there isn't one.  A file
+    /// that can't be read or parsed is an error, as it picks the embedding
+    /// model, and falling back to the default one would index with it.
+    pub async fn load() -> anyhow::Result<Self> {
+        let path = home_dir().join(".indexer.toml");
 
-            if let Ok(config) = config {
-                return config;
+        let file_contents = match tokio::fs::read_to_string(&path).await {
+            Ok(file_contents) => file_contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                return Err(anyhow!("Could not read {}: {}", path.display(), error));
             }
-        }
+        };
 
-        Self::default()
+        toml::from_str(&file_contents)
+            .map_err(|error| anyhow!("Could not parse {}: {}", path.display(), error))
     }
 }
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use sea_query::{Expr, Iden, OnConflict, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use typed_builder::TypedBuilder;
+
+use crate::context;
+
+use super::file_embedding::FileEmbeddingTable;
+
+/// SQLite caps the number of bound parameters per statement, so lookups and
+/// inserts are split into chunks of this many rows.
+const CHUNK_SIZE: usize = 500;
+
+#[derive(Iden)]
+pub enum EmbeddingCacheTable {
+    #[iden = "embedding_cache"]
+    Table,
+    Model,
+    TextHash,
+    Embedding,
+}
+
+/// A content-addressed embedding: the vector a given model produced for a
+/// given chunk of text, keyed by the hash of that text.
+#[derive(sqlx::FromRow, Debug)]
+pub struct EmbeddingCache {
+    pub text_hash: Vec<u8>,
+    pub embedding: sqlx::types::Json<Vec<f32>>,
+}
+
+#[derive(TypedBuilder)]
+pub struct CreateEmbeddingCacheProps {
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+impl EmbeddingCache {
+    pub fn hash_text(text: &str) -> Vec<u8> {
+        Sha256::digest(text.as_bytes()).to_vec()
+    }
+
+    /// Given texts, returns the cached embeddings `model` has for them, keyed
+    /// by text.  Texts without a cached embedding are absent from the result.
+    pub async fn find_many_for_texts(
+        context: &context::Context,
+        model: &str,
+        texts: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>, sqlx::Error> {
+        let texts_by_hash = texts
+            .iter()
+            .map(|text| (Self::hash_text(text), text))
+            .collect::<HashMap<_, _>>();
+        let hashes = texts_by_hash.keys().cloned().collect::<Vec<_>>();
+
+        let mut results = HashMap::new();
+        for chunk in hashes.chunks(CHUNK_SIZE) {
+            let (sql, values) = Query::select()
+                .columns([
+                    EmbeddingCacheTable::TextHash,
+                    EmbeddingCacheTable::Embedding,
+                ])
+                .from(EmbeddingCacheTable::Table)
+                .and_where(Expr::col(EmbeddingCacheTable::Model).eq(model))
+                .and_where(Expr::col(EmbeddingCacheTable::TextHash).is_in(chunk.iter().cloned()))
+                .build_sqlx(SqliteQueryBuilder);
+
+            let rows: Vec<EmbeddingCache> = sqlx::query_as_with(&sql, values)
+                .fetch_all(&context.db)
+                .await?;
+
+            for row in rows {
+                if let Some(text) = texts_by_hash.get(&row.text_hash) {
+                    results.insert(text.to_string(), row.embedding.0);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub async fn create_many(
+        context: &context::Context,
+        model: &str,
+        entries: Vec<CreateEmbeddingCacheProps>,
+    ) -> Result<(), sqlx::Error> {
+        for chunk in entries.chunks(CHUNK_SIZE) {
+            let mut builder = Query::insert();
+
+            builder
+                .into_table(EmbeddingCacheTable::Table)
+                .columns([
+                    EmbeddingCacheTable::Model,
+                    EmbeddingCacheTable::TextHash,
+                    EmbeddingCacheTable::Embedding,
+                ])
+                .on_conflict(
+                    OnConflict::columns([
+                        EmbeddingCacheTable::Model,
+                        EmbeddingCacheTable::TextHash,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                );
+
+            for entry in chunk {
+                builder.values_panic([
+                    model.into(),
+                    Self::hash_text(&entry.text).into(),
+                    json!(entry.embedding).into(),
+                ]);
+            }
+
+            let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+            sqlx::query_with(&query, values)
+                .execute(&context.db)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the embeddings cached for every model but `model`, such as the
+    /// old model's once the index is rebuilt with a new one.
+    pub async fn delete_other_models(
+        context: &context::Context,
+        model: &str,
+    ) -> Result<(), sqlx::Error> {
+        let (query, values) = Query::delete()
+            .from_table(EmbeddingCacheTable::Table)
+            .and_where(Expr::col(EmbeddingCacheTable::Model).ne(model))
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .execute(&context.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the embeddings cached for texts no indexed fragment has any
+    /// more, such as the old versions of edited paragraphs, so the cache
+    /// doesn't outgrow the index.
+    pub async fn delete_unused(context: &context::Context) -> Result<(), sqlx::Error> {
+        let (sql, values) = Query::select()
+            .distinct()
+            .column(FileEmbeddingTable::Contents)
+            .from(FileEmbeddingTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+        let used = sqlx::query_as_with::<_, (String,), _>(&sql, values)
+            .fetch_all(&context.db)
+            .await?
+            .into_iter()
+            .map(|(contents,)| Self::hash_text(&contents))
+            .collect::<HashSet<_>>();
+
+        let (sql, values) = Query::select()
+            .distinct()
+            .column(EmbeddingCacheTable::TextHash)
+            .from(EmbeddingCacheTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+        let unused = sqlx::query_as_with::<_, (Vec<u8>,), _>(&sql, values)
+            .fetch_all(&context.db)
+            .await?
+            .into_iter()
+            .map(|(hash,)| hash)
+            .filter(|x| !used.contains(x))
+            .collect::<Vec<_>>();
+
+        for chunk in unused.chunks(CHUNK_SIZE) {
+            let (query, values) = Query::delete()
+                .from_table(EmbeddingCacheTable::Table)
+                .and_where(Expr::col(EmbeddingCacheTable::TextHash).is_in(chunk.iter().cloned()))
+                .build_sqlx(SqliteQueryBuilder);
+
+            sqlx::query_with(&query, values)
+                .execute(&context.db)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
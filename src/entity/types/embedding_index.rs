@@ -0,0 +1,253 @@
+use anyhow::{anyhow, bail};
+use sea_query::{Asterisk, Expr, Func, Iden, OnConflict, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::SqliteConnection;
+
+use crate::context;
+
+use super::file_embedding::FileEmbeddingTable;
+
+#[derive(Iden)]
+pub enum EmbeddingIndexTable {
+    #[iden = "embedding_index"]
+    Table,
+    Id,
+    Model,
+    Dimension,
+    Generation,
+}
+
+/// The model the indexed embeddings came from, and the length of its
+/// vectors.  Vectors from different models can't be compared, so everything
+/// in the index has to come from the same one.
+#[derive(sqlx::FromRow, Debug)]
+pub struct EmbeddingIndex {
+    pub model: String,
+    pub dimension: i64,
+
+    /// Counts the rebuilds of the vector index, each of which gets a vss
+    /// table of its own.
+    pub generation: i64,
+}
+
+/// The name of the vss table a generation of the vector index is kept in.
+pub fn vss_table_name(generation: i64) -> String {
+    match generation {
+        0 => "vss_file_embeddings".to_string(),
+        _ => format!("vss_file_embeddings_{}", generation),
+    }
+}
+
+/// Creates an empty vss table for vectors of `dimension`.
+pub async fn create_vss_table(
+    conn: &mut SqliteConnection,
+    table: &str,
+    dimension: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "CREATE VIRTUAL TABLE {table} using vss0 (embedding({dimension}))"
+    ))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Recreates the triggers that copy fragments' vectors into the vss table, so
+/// they copy them into `table`.
+pub async fn point_triggers_at(
+    conn: &mut SqliteConnection,
+    table: &str,
+) -> Result<(), sqlx::Error> {
+    for statement in [
+        "DROP TRIGGER IF EXISTS update_vss_file_embeddings".to_string(),
+        "DROP TRIGGER IF EXISTS delete_vss_file_embeddings".to_string(),
+        format!(
+            r#"CREATE TRIGGER update_vss_file_embeddings
+            AFTER INSERT ON file_embeddings
+            FOR EACH ROW
+            BEGIN
+                DELETE FROM {table}
+                WHERE rowid = NEW.rowid;
+
+                INSERT INTO {table} (rowid, embedding)
+                SELECT NEW.rowid, NEW.embedding
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM {table} WHERE rowid = NEW.rowid
+                );
+            END"#
+        ),
+        format!(
+            r#"CREATE TRIGGER delete_vss_file_embeddings
+            AFTER DELETE ON file_embeddings
+            FOR EACH ROW
+            BEGIN
+                DELETE FROM {table}
+                WHERE rowid = OLD.rowid;
+            END"#
+        ),
+    ] {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+impl EmbeddingIndex {
+    pub async fn find(context: &context::Context) -> Result<Option<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(EmbeddingIndexTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_optional(&context.db)
+            .await
+    }
+
+    /// The vss table the vector index is currently kept in.
+    pub fn vss_table(&self) -> String {
+        vss_table_name(self.generation)
+    }
+
+    /// Makes sure the index holds vectors from `model`, without changing it,
+    /// as searching shouldn't.
+    pub async fn check(
+        context: &context::Context,
+        model: &str,
+        dimension: usize,
+    ) -> anyhow::Result<()> {
+        match Self::find(context).await? {
+            Some(index) if index.model == model && index.dimension == dimension as i64 => Ok(()),
+            Some(index) => Err(index.other_model(model)),
+            None => bail!("Nothing has been indexed yet.  Run `indexer run` first."),
+        }
+    }
+
+    /// Makes sure vectors from `model` still belong in the index, which
+    /// `indexer reembed` may have moved on to another model since the
+    /// embedder was loaded.  Takes a connection so that it can be checked
+    /// in the transaction the vectors are written in.
+    pub async fn check_current(conn: &mut SqliteConnection, model: &str) -> anyhow::Result<()> {
+        let (sql, values) = Query::select()
+            .column(EmbeddingIndexTable::Model)
+            .from(EmbeddingIndexTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+        let index_model: Option<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        match index_model {
+            Some((index_model,)) if index_model != model => bail!(
+                "The index was re-embedded with `{}` while this indexer was \
+                 embedding with `{}`.  Restart it to embed with the new model.",
+                index_model,
+                model
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn other_model(&self, model: &str) -> anyhow::Error {
+        anyhow!(
+            "The index holds embeddings from `{}`, but `{}` is configured.  \
+             Run `indexer reembed` to re-embed it with the new model, or \
+             configure the old one again in `.indexer.toml`.",
+            self.model,
+            model
+        )
+    }
+
+    /// Sets the index up for `model` before anything is indexed with it.  An
+    /// empty index is rebuilt for the model's dimension; one already holding
+    /// another model's vectors is refused rather than mixed with them.
+    pub async fn ensure(
+        context: &context::Context,
+        model: &str,
+        dimension: usize,
+    ) -> anyhow::Result<()> {
+        let index = Self::find(context).await?;
+        if index
+            .as_ref()
+            .is_some_and(|x| x.model == model && x.dimension == dimension as i64)
+        {
+            return Ok(());
+        }
+
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Asterisk)))
+            .from(FileEmbeddingTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+        let (count,): (i64,) = sqlx::query_as_with(&sql, values)
+            .fetch_one(&context.db)
+            .await?;
+
+        if let (Some(index), true) = (&index, count > 0) {
+            return Err(index.other_model(model));
+        }
+
+        Self::create(context, model, dimension, index.map_or(0, |x| x.generation)).await
+    }
+
+    /// Records the model, and recreates the empty vector index for its
+    /// dimension.
+    async fn create(
+        context: &context::Context,
+        model: &str,
+        dimension: usize,
+        generation: i64,
+    ) -> anyhow::Result<()> {
+        let mut transaction = context.db.begin().await?;
+
+        let table = vss_table_name(generation);
+        sqlx::query(&format!("DROP TABLE IF EXISTS {table}"))
+            .execute(&mut *transaction)
+            .await?;
+        create_vss_table(&mut transaction, &table, dimension as i64).await?;
+
+        let (sql, values) = Query::insert()
+            .into_table(EmbeddingIndexTable::Table)
+            .columns([
+                EmbeddingIndexTable::Id,
+                EmbeddingIndexTable::Model,
+                EmbeddingIndexTable::Dimension,
+                EmbeddingIndexTable::Generation,
+            ])
+            .values_panic([
+                1.into(),
+                model.into(),
+                (dimension as i64).into(),
+                generation.into(),
+            ])
+            .on_conflict(
+                OnConflict::column(EmbeddingIndexTable::Id)
+                    .update_columns([EmbeddingIndexTable::Model, EmbeddingIndexTable::Dimension])
+                    .to_owned(),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Makes `model`'s vectors, kept in the vss table of `generation`, the
+    /// index.
+    pub async fn update(
+        conn: &mut SqliteConnection,
+        model: &str,
+        dimension: i64,
+        generation: i64,
+    ) -> Result<(), sqlx::Error> {
+        let (sql, values) = Query::update()
+            .table(EmbeddingIndexTable::Table)
+            .values([
+                (EmbeddingIndexTable::Model, model.into()),
+                (EmbeddingIndexTable::Dimension, dimension.into()),
+                (EmbeddingIndexTable::Generation, generation.into()),
+            ])
+            .build_sqlx(SqliteQueryBuilder);
+        sqlx::query_with(&sql, values).execute(conn).await?;
+        Ok(())
+    }
+}
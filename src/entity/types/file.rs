@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use async_trait::async_trait;
 use sea_query::{Asterisk, Expr, Iden, OnConflict, Query, SqliteQueryBuilder};
 use sea_query_binder::SqlxBinder;
+use sqlx::SqliteConnection;
 use typed_builder::TypedBuilder;
 
 use crate::{
@@ -70,7 +71,7 @@ pub struct CreateFileProps {
 
 impl File {
     pub async fn create_many(
-        context: &context::Context,
+        conn: &mut SqliteConnection,
         files: Vec<CreateFileProps>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         if files.is_empty() {
@@ -100,7 +101,28 @@ impl File {
         let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
 
         sqlx::query_as_with(&query, values)
-            .fetch_all(&context.db)
+            .fetch_all(&mut *conn)
             .await
     }
+
+    /// Forgets files that no longer exist, along with their embeddings and
+    /// metadata.
+    pub async fn delete_many(
+        context: &context::Context,
+        paths: Vec<PathBuf>,
+    ) -> Result<(), sqlx::Error> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let (query, values) = Query::delete()
+            .from_table(FileTable::Table)
+            .and_where(Expr::col(FileTable::Path).is_in(paths.into_iter().map(FilePath::new)))
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .execute(&context.db)
+            .await?;
+        Ok(())
+    }
 }
@@ -1,16 +1,31 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, os::unix::ffi::OsStrExt, path::PathBuf, str::FromStr};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
-use sea_query::{Asterisk, Expr, Iden, Query, SqliteQueryBuilder};
+use globset::{GlobBuilder, GlobSetBuilder};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sea_query::{
+    Alias, Asterisk, Cond, Expr, Iden, IntoColumnRef, Order, Query, SelectStatement, SimpleExpr,
+    SqliteQueryBuilder,
+};
 use sea_query_binder::SqlxBinder;
 use serde_json::json;
+use sqlx::SqliteConnection;
 use typed_builder::TypedBuilder;
 
 use crate::{
     context,
-    entity::{columns::FilePath, Entity},
-    services::embeddings::EmbeddingsService,
+    entity::{
+        columns::FilePath,
+        types::{embedding_index::EmbeddingIndex, file::FileTable},
+        Entity,
+    },
+    services::{
+        embeddings::EmbeddingsService,
+        parsers::{FragmentKind, FragmentRange, Metadata},
+        ranking,
+    },
 };
 
 #[derive(Iden)]
@@ -20,6 +35,17 @@ pub enum FileEmbeddingTable {
     FilePath,
     Embedding,
     Contents,
+    StartLine,
+    EndLine,
+    StartByte,
+    EndByte,
+    Page,
+    HeadingPath,
+    Chapter,
+    Language,
+    Cell,
+    Metadata,
+    Kind,
 }
 
 #[derive(sqlx::FromRow, Debug)]
@@ -27,6 +53,29 @@ pub struct FileEmbedding {
     pub file_path: FilePath,
     pub embedding: sqlx::types::Json<Vec<f32>>,
     pub contents: String,
+
+    /// 1-indexed, inclusive line range of the fragment within its file.
+    pub start_line: i64,
+    pub end_line: i64,
+
+    /// The 1-indexed page the fragment is on, for paginated formats.
+    pub page: Option<i64>,
+
+    /// The headings enclosing the fragment, outermost first.
+    pub heading_path: sqlx::types::Json<Vec<String>>,
+
+    /// The chapter the fragment is in, for formats made of several documents.
+    pub chapter: Option<String>,
+
+    /// The programming language of the fragment, for source code.
+    pub language: Option<String>,
+
+    /// The 1-indexed cell the fragment is in, for notebooks.
+    pub cell: Option<i64>,
+
+    /// The metadata of the fragment and its file, each key holding a list
+    /// of values.
+    pub metadata: sqlx::types::Json<BTreeMap<String, Vec<String>>>,
 }
 
 #[async_trait]
@@ -62,60 +111,640 @@ pub struct CreateFileEmbeddingProps {
     pub file_path: PathBuf,
     pub embedding: Vec<f32>,
     pub contents: String,
+    pub range: FragmentRange,
+    pub page: Option<u32>,
+    pub heading_path: Vec<String>,
+    pub chapter: Option<String>,
+    pub language: Option<String>,
+    pub cell: Option<u32>,
+    pub metadata: Metadata,
+    pub kind: FragmentKind,
+}
+
+static METADATA_FILTER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([\w-]+)\s*(!=|>=|<=|=|>|<)\s*(.*)$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOperator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A condition on a fragment's metadata, written as `key=value`, with `!=`,
+/// `<`, `<=`, `>` and `>=` also available.  Values compare as text, which
+/// orders `YYYY-MM-DD` dates correctly.  A key holding several values (tags)
+/// matches if any of them does, except for `!=`, which matches if none of
+/// them is equal.
+#[derive(Debug, Clone)]
+pub struct MetadataFilter {
+    pub key: String,
+    pub operator: FilterOperator,
+    pub value: String,
+}
+
+impl FromStr for MetadataFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let captures = METADATA_FILTER_REGEX
+            .captures(filter.trim())
+            .ok_or_else(|| anyhow!("Filters look like `key=value`, not `{filter}`"))?;
+
+        let operator = match &captures[2] {
+            "=" => FilterOperator::Eq,
+            "!=" => FilterOperator::Ne,
+            "<" => FilterOperator::Lt,
+            "<=" => FilterOperator::Le,
+            ">" => FilterOperator::Gt,
+            _ => FilterOperator::Ge,
+        };
+
+        Ok(MetadataFilter {
+            key: captures[1].to_string(),
+            operator,
+            value: captures[3].to_string(),
+        })
+    }
+}
+
+impl MetadataFilter {
+    fn to_expr(&self) -> SimpleExpr {
+        let path = format!("$.\"{}\"", self.key);
+        let condition = |operator: &str| {
+            format!("EXISTS (SELECT 1 FROM json_each(metadata, ?) WHERE value {operator} ?)")
+        };
+
+        let sql = match self.operator {
+            FilterOperator::Eq => condition("="),
+            FilterOperator::Ne => format!("NOT {}", condition("=")),
+            FilterOperator::Lt => condition("<"),
+            FilterOperator::Le => condition("<="),
+            FilterOperator::Gt => condition(">"),
+            FilterOperator::Ge => condition(">="),
+        };
+
+        Expr::cust_with_values(sql, [path, self.value.to_string()])
+    }
+}
+
+/// Restricts which fragments a search considers.
+#[derive(TypedBuilder, Default, Debug)]
+pub struct SearchFilters {
+    /// Only consider fragments in one of these languages.
+    #[builder(default)]
+    pub languages: Vec<String>,
+
+    /// Only consider fragments of source code.
+    #[builder(default)]
+    pub code_only: bool,
+
+    /// Only consider fragments whose metadata passes all of these.
+    #[builder(default)]
+    pub metadata: Vec<MetadataFilter>,
+
+    /// Only consider fragments under a heading with one of these TODO
+    /// keywords.
+    #[builder(default)]
+    pub todo_keywords: Vec<String>,
+
+    /// Only consider files under one of these paths.
+    #[builder(default)]
+    pub paths: Vec<PathBuf>,
+
+    /// Only consider files whose path matches one of these globs.  `*`
+    /// doesn't cross directories, and globs match the whole indexed path, so
+    /// start them with `**/` to match anywhere.
+    #[builder(default)]
+    pub globs: Vec<String>,
+
+    /// Never consider these files, e.g. the file a search for related notes
+    /// starts from.
+    #[builder(default)]
+    pub excluded_paths: Vec<PathBuf>,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty()
+            && !self.code_only
+            && self.metadata.is_empty()
+            && self.todo_keywords.is_empty()
+            && self.paths.is_empty()
+            && self.globs.is_empty()
+            && self.excluded_paths.is_empty()
+    }
+
+    /// Matches the paths under one of the path scopes.  Paths are stored as
+    /// bytes, which SQLite compares one by one, so everything under `dir`
+    /// sorts between `dir/` and `dir0`, `0` being the byte after `/`.
+    fn path_scope(&self, column: impl IntoColumnRef) -> Cond {
+        let column = column.into_column_ref();
+        let mut condition = Cond::any();
+        for path in &self.paths {
+            let dir = path.as_os_str().as_bytes();
+            let dir = dir.strip_suffix(b"/").unwrap_or(dir);
+            let bound = |last: u8| [dir, &[last]].concat();
+
+            condition = condition
+                .add(Expr::col(column.clone()).eq(FilePath::new(path.to_path_buf())))
+                .add(
+                    Expr::col(column.clone())
+                        .gte(bound(b'/'))
+                        .and(Expr::col(column.clone()).lt(bound(b'0'))),
+                );
+        }
+        condition
+    }
+
+    /// Finds the indexed files, within the path scopes, that match one of
+    /// the globs.  Globs can't be matched by SQLite, so they're applied to
+    /// the list of files instead of the fragments.
+    async fn glob_paths(&self, context: &context::Context) -> anyhow::Result<Vec<PathBuf>> {
+        let mut globs = GlobSetBuilder::new();
+        for glob in &self.globs {
+            globs.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+        }
+        let globs = globs.build()?;
+
+        let mut builder = Query::select();
+        builder.column(FileTable::Path).from(FileTable::Table);
+        if !self.paths.is_empty() {
+            builder.cond_where(self.path_scope(FileTable::Path));
+        }
+        let (sql, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        let rows: Vec<(FilePath,)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(path,)| path.0)
+            .filter(|path| globs.is_match(path))
+            .collect())
+    }
+}
+
+/// SQLite caps the number of bound parameters per statement, and each
+/// fragment binds fourteen, so inserts are split into chunks of this many
+/// rows.
+const CHUNK_SIZE: usize = 500;
+
+/// The number of results a search returns.
+pub const SEARCH_LIMIT: u64 = 3;
+
+/// How many times more candidates than results are fetched for re-ranking
+/// by maximal marginal relevance.
+const MMR_CANDIDATES_FACTOR: u64 = 5;
+
+/// How a file is scored from the distances of its fragments to the query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileScore {
+    /// The distance of its nearest fragment.
+    #[default]
+    Best,
+
+    /// The mean distance of the fragments shown for it, which favours files
+    /// with several fragments near the query over a single lucky one.
+    Mean,
+}
+
+impl FromStr for FileScore {
+    type Err = anyhow::Error;
+
+    fn from_str(score: &str) -> Result<Self, Self::Err> {
+        match score {
+            "best" => Ok(FileScore::Best),
+            "mean" => Ok(FileScore::Mean),
+            _ => Err(anyhow!("File scores are `best` or `mean`, not `{score}`")),
+        }
+    }
+}
+
+impl FileScore {
+    fn aggregate(&self) -> &'static str {
+        match self {
+            FileScore::Best => "MIN",
+            FileScore::Mean => "AVG",
+        }
+    }
+}
+
+/// How search results are grouped by file.
+#[derive(TypedBuilder, Debug)]
+pub struct FileGrouping {
+    /// The number of distinct files to return.
+    #[builder(default = SEARCH_LIMIT)]
+    pub files: u64,
+
+    /// The number of fragments to return for each file.
+    #[builder(default = 2)]
+    pub snippets: u64,
+
+    #[builder(default)]
+    pub score: FileScore,
+}
+
+/// A file matching a search, with its fragments nearest to the query,
+/// nearest first.
+#[derive(Debug)]
+pub struct FileMatches {
+    pub file_path: PathBuf,
+    pub fragments: Vec<FileEmbedding>,
 }
 
 impl FileEmbedding {
     pub async fn create_many(
-        context: &context::Context,
+        conn: &mut SqliteConnection,
         files: Vec<CreateFileEmbeddingProps>,
     ) -> Result<(), sqlx::Error> {
-        if files.is_empty() {
-            return Ok(());
-        }
+        for chunk in files.chunks(CHUNK_SIZE) {
+            let mut builder = Query::insert();
 
-        let mut builder = Query::insert();
+            builder.into_table(FileEmbeddingTable::Table).columns([
+                FileEmbeddingTable::FilePath,
+                FileEmbeddingTable::Embedding,
+                FileEmbeddingTable::Contents,
+                FileEmbeddingTable::StartLine,
+                FileEmbeddingTable::EndLine,
+                FileEmbeddingTable::StartByte,
+                FileEmbeddingTable::EndByte,
+                FileEmbeddingTable::Page,
+                FileEmbeddingTable::HeadingPath,
+                FileEmbeddingTable::Chapter,
+                FileEmbeddingTable::Language,
+                FileEmbeddingTable::Cell,
+                FileEmbeddingTable::Metadata,
+                FileEmbeddingTable::Kind,
+            ]);
 
-        builder.into_table(FileEmbeddingTable::Table).columns([
-            FileEmbeddingTable::FilePath,
-            FileEmbeddingTable::Embedding,
-            FileEmbeddingTable::Contents,
-        ]);
+            for file in chunk {
+                builder.values_panic([
+                    FilePath::new(file.file_path.to_path_buf()).into(),
+                    json!(file.embedding).into(),
+                    file.contents.to_string().into(),
+                    (file.range.start_line as i64).into(),
+                    (file.range.end_line as i64).into(),
+                    (file.range.start_byte as i64).into(),
+                    (file.range.end_byte as i64).into(),
+                    file.page.into(),
+                    json!(file.heading_path).into(),
+                    file.chapter.clone().into(),
+                    file.language.clone().into(),
+                    file.cell.into(),
+                    file.metadata.to_json().to_string().into(),
+                    file.kind.as_str().into(),
+                ]);
+            }
 
-        for file in files {
-            builder.values_panic([
-                FilePath::new(file.file_path).into(),
-                json!(file.embedding).into(),
-                file.contents.into(),
-            ]);
-        }
+            let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
 
-        let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
+            sqlx::query_with(&query, values).execute(&mut *conn).await?;
+        }
 
-        let _ = sqlx::query_with(&query, values)
-            .execute(&context.db)
-            .await?;
         Ok(())
     }
 
+    /// Finds the `limit` fragments nearest to the query.  With a
+    /// `diversity`, more candidates are fetched and then re-ranked by maximal
+    /// marginal relevance, so near-duplicates don't crowd out other aspects
+    /// of the query.
     pub async fn search(
         context: &context::Context,
+        embeddings: &EmbeddingsService,
         query: &str,
+        filters: &SearchFilters,
+        limit: u64,
+        diversity: Option<f32>,
     ) -> anyhow::Result<Vec<FileEmbedding>> {
-        let embedding_svc = EmbeddingsService::try_new()?;
-        let embedded_query = json!(embedding_svc.embedding(query).await?);
+        let embedding = embeddings.query_embedding(query).await?;
+        let embedded_query = json!(embedding);
+
+        let candidate_limit = match diversity {
+            Some(_) => limit * MMR_CANDIDATES_FACTOR,
+            None => limit,
+        };
+
+        let candidates = if filters.is_empty() {
+            Self::search_nearest(context, &embedded_query, candidate_limit).await?
+        } else {
+            Self::search_filtered(context, &embedded_query, filters, candidate_limit).await?
+        };
+
+        Ok(match diversity {
+            Some(diversity) => ranking::maximal_marginal_relevance(
+                &embedding,
+                candidates,
+                |x| &x.embedding,
+                limit as usize,
+                diversity,
+            ),
+            None => candidates,
+        })
+    }
+
+    /// Finds the fragments nearest to the query with the vss index.
+    async fn search_nearest(
+        context: &context::Context,
+        embedded_query: &serde_json::Value,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FileEmbedding>> {
+        // Rebuilds of the index go into new vss tables, so the current one
+        // has to be looked up.
+        let vss_table = EmbeddingIndex::find(context)
+            .await?
+            .context("The embedding index has not been set up.")?
+            .vss_table();
 
         sqlx::query_as(&format!(
-            r#"SELECT f.file_path, f.embedding, f.contents
+            r#"SELECT f.file_path, f.embedding, f.contents,
+                    f.start_line, f.end_line, f.page, f.heading_path, f.chapter,
+                    f.language, f.cell, f.metadata
                 FROM file_embeddings f
-                INNER JOIN vss_file_embeddings v ON (v.rowid = f.rowid)
+                INNER JOIN {vss_table} v ON (v.rowid = f.rowid)
                 WHERE vss_search(
                     v.embedding,
-                    vss_search_params('{embedded_query}', 3)
+                    vss_search_params('{embedded_query}', {limit})
                 )
-                LIMIT 3"#
+                LIMIT {limit}"#
         ))
         .fetch_all(&context.db)
         .await
         .context("Query failed")
     }
+
+    /// Returns the headings of every open TODO item, soonest deadline or
+    /// scheduled date first, then by file and line.
+    pub async fn find_open_items(context: &context::Context) -> anyhow::Result<Vec<FileEmbedding>> {
+        let open = MetadataFilter {
+            key: "todo_state".to_string(),
+            operator: FilterOperator::Eq,
+            value: "open".to_string(),
+        };
+
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(FileEmbeddingTable::Table)
+            .and_where(Expr::col(FileEmbeddingTable::Kind).eq(FragmentKind::Heading.as_str()))
+            .and_where(open.to_expr())
+            .order_by_expr(
+                Expr::cust(
+                    "COALESCE(json_extract(metadata, '$.deadline[0]'), \
+                     json_extract(metadata, '$.scheduled[0]'), '9999')",
+                ),
+                Order::Asc,
+            )
+            .order_by(FileEmbeddingTable::FilePath, Order::Asc)
+            .order_by(FileEmbeddingTable::StartLine, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+            .context("Query failed")
+    }
+
+    /// The vss index can only return the nearest neighbours of the whole
+    /// table, so filtering its results could leave nothing.  Instead, rank
+    /// just the fragments that pass the filters by their exact distance to
+    /// the query.
+    async fn search_filtered(
+        context: &context::Context,
+        embedded_query: &serde_json::Value,
+        filters: &SearchFilters,
+        limit: u64,
+    ) -> anyhow::Result<Vec<FileEmbedding>> {
+        let Some(mut builder) = Self::filtered_select(context, filters).await? else {
+            return Ok(vec![]);
+        };
+
+        builder
+            .order_by_expr(
+                Expr::cust_with_values(
+                    "vss_distance_l2(embedding, ?)",
+                    [embedded_query.to_string()],
+                ),
+                Order::Asc,
+            )
+            .limit(limit);
+
+        let (sql, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+            .context("Query failed")
+    }
+
+    /// Selects the fragments that pass the filters, or returns `None` if
+    /// the path and glob scopes leave no files to search.
+    async fn filtered_select(
+        context: &context::Context,
+        filters: &SearchFilters,
+    ) -> anyhow::Result<Option<SelectStatement>> {
+        let mut builder = Query::select();
+
+        // Scoping happens before the nearest neighbours are picked, so a
+        // search still returns as many results as the scope has to give.
+        if !filters.paths.is_empty() {
+            builder.cond_where(filters.path_scope(FileEmbeddingTable::FilePath));
+        }
+
+        if !filters.globs.is_empty() {
+            let paths = filters.glob_paths(context).await?;
+            if paths.is_empty() {
+                return Ok(None);
+            }
+
+            builder.and_where(
+                Expr::col(FileEmbeddingTable::FilePath).is_in(paths.into_iter().map(FilePath::new)),
+            );
+        }
+
+        builder
+            .columns([
+                FileEmbeddingTable::FilePath,
+                FileEmbeddingTable::Embedding,
+                FileEmbeddingTable::Contents,
+                FileEmbeddingTable::StartLine,
+                FileEmbeddingTable::EndLine,
+                FileEmbeddingTable::Page,
+                FileEmbeddingTable::HeadingPath,
+                FileEmbeddingTable::Chapter,
+                FileEmbeddingTable::Language,
+                FileEmbeddingTable::Cell,
+                FileEmbeddingTable::Metadata,
+            ])
+            .from(FileEmbeddingTable::Table);
+
+        if !filters.languages.is_empty() {
+            builder.and_where(
+                Expr::col(FileEmbeddingTable::Language).is_in(filters.languages.iter().cloned()),
+            );
+        }
+
+        if filters.code_only {
+            builder.and_where(Expr::col(FileEmbeddingTable::Language).is_not_null());
+        }
+
+        for filter in &filters.metadata {
+            builder.and_where(filter.to_expr());
+        }
+
+        if !filters.todo_keywords.is_empty() {
+            let placeholders = vec!["?"; filters.todo_keywords.len()].join(", ");
+            builder.and_where(Expr::cust_with_values(
+                format!(
+                    "EXISTS (SELECT 1 FROM json_each(metadata, '$.todo') WHERE value IN ({placeholders}))"
+                ),
+                filters.todo_keywords.iter().cloned(),
+            ));
+        }
+
+        if !filters.excluded_paths.is_empty() {
+            builder.and_where(
+                Expr::col(FileEmbeddingTable::FilePath)
+                    .is_not_in(filters.excluded_paths.iter().cloned().map(FilePath::new)),
+            );
+        }
+
+        Ok(Some(builder))
+    }
+
+    /// Ranks files by the distances of their fragments to `embedding`,
+    /// returning the nearest files along with their nearest fragments.
+    /// Every fragment is scored exactly, as in a filtered search, so the
+    /// files are distinct however many fragments one file has near the query.
+    pub async fn search_files(
+        context: &context::Context,
+        embedding: &[f32],
+        filters: &SearchFilters,
+        grouping: &FileGrouping,
+    ) -> anyhow::Result<Vec<FileMatches>> {
+        let Some(mut builder) = Self::filtered_select(context, filters).await? else {
+            return Ok(vec![]);
+        };
+
+        builder.expr_as(
+            Expr::cust_with_values(
+                "vss_distance_l2(embedding, ?)",
+                [json!(embedding).to_string()],
+            ),
+            Alias::new("distance"),
+        );
+
+        let (scored, values) = builder.build_sqlx(SqliteQueryBuilder);
+        let FileGrouping {
+            files,
+            snippets,
+            score,
+        } = grouping;
+        let aggregate = score.aggregate();
+        let sql = format!(
+            r#"WITH scored AS ({scored}),
+                ranked AS (
+                    SELECT *,
+                        ROW_NUMBER() OVER (PARTITION BY file_path ORDER BY distance) AS file_rank
+                    FROM scored
+                ),
+                files AS (
+                    SELECT file_path, {aggregate}(distance) AS file_distance
+                    FROM ranked
+                    WHERE file_rank <= {snippets}
+                    GROUP BY file_path
+                    ORDER BY file_distance
+                    LIMIT {files}
+                )
+                SELECT ranked.*, files.file_distance
+                FROM ranked
+                INNER JOIN files ON (files.file_path = ranked.file_path)
+                WHERE ranked.file_rank <= {snippets}
+                ORDER BY files.file_distance, ranked.file_path, ranked.file_rank"#
+        );
+
+        let fragments: Vec<FileEmbedding> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+            .context("Query failed")?;
+
+        let mut files: Vec<FileMatches> = vec![];
+        for fragment in fragments {
+            match files.last_mut() {
+                Some(file) if file.file_path == fragment.file_path.0 => {
+                    file.fragments.push(fragment)
+                }
+                _ => files.push(FileMatches {
+                    file_path: fragment.file_path.0.to_path_buf(),
+                    fragments: vec![fragment],
+                }),
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// The embedding of a file, averaged over its fragments, or of just the
+    /// fragments covering a line of it.  Used as the query when searching
+    /// for notes like it.
+    pub async fn find_embedding_at(
+        context: &context::Context,
+        path: PathBuf,
+        line: Option<usize>,
+    ) -> anyhow::Result<Vec<f32>> {
+        let mut builder = Query::select();
+        builder
+            .column(FileEmbeddingTable::Embedding)
+            .from(FileEmbeddingTable::Table)
+            .and_where(Expr::col(FileEmbeddingTable::FilePath).eq(FilePath::new(path.clone())));
+
+        if let Some(line) = line {
+            builder
+                .and_where(Expr::col(FileEmbeddingTable::StartLine).lte(line as i64))
+                .and_where(Expr::col(FileEmbeddingTable::EndLine).gte(line as i64));
+        }
+
+        let (sql, values) = builder.build_sqlx(SqliteQueryBuilder);
+        let embeddings: Vec<(sqlx::types::Json<Vec<f32>>,)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+            .context("Query failed")?;
+
+        let Some((first,)) = embeddings.first() else {
+            return Err(match line {
+                Some(line) => anyhow!("Nothing indexed at {}:{}", path.display(), line),
+                None => anyhow!("{} is not indexed", path.display()),
+            });
+        };
+
+        let mut mean = vec![0.0; first.len()];
+        for (embedding,) in &embeddings {
+            for (sum, x) in mean.iter_mut().zip(embedding.iter()) {
+                *sum += x;
+            }
+        }
+
+        // Fragment embeddings are unit length, so the mean is scaled back up
+        // to compare with them.
+        let norm = mean.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            mean.iter_mut().for_each(|x| *x /= norm);
+        }
+
+        Ok(mean)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_score_parses_known_scores() {
+        assert_eq!("best".parse::<FileScore>().unwrap(), FileScore::Best);
+        assert_eq!("mean".parse::<FileScore>().unwrap(), FileScore::Mean);
+        assert!("max".parse::<FileScore>().is_err());
+    }
 }
@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use sea_query::{Asterisk, Cond, Expr, Iden, Order, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::SqliteConnection;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    context,
+    entity::{
+        columns::FilePath,
+        types::{file::FileTable, org_node::OrgNodeTable},
+        Entity,
+    },
+    services::parsers::LinkTarget,
+};
+
+#[derive(Iden)]
+pub enum FileLinkTable {
+    #[iden = "file_link"]
+    Table,
+    SourcePath,
+    SourceLine,
+    TargetPath,
+    TargetId,
+    Description,
+}
+
+/// A link from a fragment of one file to another file or Org ID.
+#[derive(sqlx::FromRow, Debug)]
+pub struct FileLink {
+    pub source_path: FilePath,
+
+    /// The first line of the fragment the link is in.
+    pub source_line: i64,
+
+    /// The file linked to, if the link is to a file.  It may not be indexed,
+    /// or exist at all.
+    pub target_path: Option<FilePath>,
+
+    /// The Org ID linked to, if the link is to an ID.
+    pub target_id: Option<String>,
+
+    pub description: Option<String>,
+}
+
+#[async_trait]
+impl Entity for FileLink {
+    type ID = FilePath;
+
+    fn get_id(&self) -> Self::ID {
+        self.source_path.clone()
+    }
+
+    fn name() -> &'static str {
+        "file_link"
+    }
+
+    async fn find_many(
+        context: &context::Context,
+        ids: &[Self::ID],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(FileLinkTable::Table)
+            .and_where(Expr::col(FileLinkTable::SourcePath).is_in(ids))
+            .order_by(FileLinkTable::SourcePath, Order::Asc)
+            .order_by(FileLinkTable::SourceLine, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+}
+
+#[derive(TypedBuilder)]
+pub struct CreateFileLinkProps {
+    pub source_path: PathBuf,
+    pub source_line: usize,
+    pub target: LinkTarget,
+    pub description: Option<String>,
+}
+
+impl FileLink {
+    pub async fn create_many(
+        conn: &mut SqliteConnection,
+        links: Vec<CreateFileLinkProps>,
+    ) -> Result<(), sqlx::Error> {
+        if links.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = Query::insert();
+
+        builder.into_table(FileLinkTable::Table).columns([
+            FileLinkTable::SourcePath,
+            FileLinkTable::SourceLine,
+            FileLinkTable::TargetPath,
+            FileLinkTable::TargetId,
+            FileLinkTable::Description,
+        ]);
+
+        for link in links {
+            let (target_path, target_id) = match link.target {
+                LinkTarget::File(path) => (FilePath::new(path).into(), None),
+                LinkTarget::Id(id) => (sea_query::Value::Bytes(None), Some(id)),
+            };
+
+            builder.values_panic([
+                FilePath::new(link.source_path).into(),
+                (link.source_line as i64).into(),
+                target_path.into(),
+                target_id.into(),
+                link.description.into(),
+            ]);
+        }
+
+        let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Returns the links made to a file, either to its path or to the ID of
+    /// one of its Org nodes.
+    pub async fn find_backlinks(
+        context: &context::Context,
+        path: PathBuf,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let path = FilePath::new(path);
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(FileLinkTable::Table)
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(FileLinkTable::TargetPath).eq(path.clone()))
+                    .add(
+                        Expr::col(FileLinkTable::TargetId).in_subquery(
+                            Query::select()
+                                .column(OrgNodeTable::Id)
+                                .from(OrgNodeTable::Table)
+                                .and_where(Expr::col(OrgNodeTable::FilePath).eq(path))
+                                .to_owned(),
+                        ),
+                    ),
+            )
+            .order_by(FileLinkTable::SourcePath, Order::Asc)
+            .order_by(FileLinkTable::SourceLine, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+
+    /// Returns the links made to an Org node by its ID.
+    pub async fn find_backlinks_to_id(
+        context: &context::Context,
+        id: String,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(FileLinkTable::Table)
+            .and_where(Expr::col(FileLinkTable::TargetId).eq(id))
+            .order_by(FileLinkTable::SourcePath, Order::Asc)
+            .order_by(FileLinkTable::SourceLine, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+
+    /// Returns the links to files that aren't indexed and to Org IDs no
+    /// indexed node has, optionally just those made from one file.  Links to
+    /// files may still point at files that exist but aren't indexed, like
+    /// images.
+    pub async fn find_unresolved(
+        context: &context::Context,
+        source_path: Option<PathBuf>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut builder = Query::select();
+
+        builder
+            .column(Asterisk)
+            .from(FileLinkTable::Table)
+            .cond_where(
+                Cond::any()
+                    .add(
+                        Expr::col(FileLinkTable::TargetPath).not_in_subquery(
+                            Query::select()
+                                .column(FileTable::Path)
+                                .from(FileTable::Table)
+                                .to_owned(),
+                        ),
+                    )
+                    .add(
+                        Expr::col(FileLinkTable::TargetId).not_in_subquery(
+                            Query::select()
+                                .column(OrgNodeTable::Id)
+                                .from(OrgNodeTable::Table)
+                                .to_owned(),
+                        ),
+                    ),
+            )
+            .order_by(FileLinkTable::SourcePath, Order::Asc)
+            .order_by(FileLinkTable::SourceLine, Order::Asc);
+
+        if let Some(source_path) = source_path {
+            builder.and_where(Expr::col(FileLinkTable::SourcePath).eq(FilePath::new(source_path)));
+        }
+
+        let (sql, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use sea_query::{Expr, Iden, OnConflict, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use typed_builder::TypedBuilder;
+
+use crate::{context, entity::columns::FilePath};
+
+#[derive(Iden)]
+pub enum GitRepositoryTable {
+    #[iden = "git_repository"]
+    Table,
+    Path,
+    LastCommit,
+}
+
+/// A git repository whose history is indexed, and how far: the hash of the
+/// commit HEAD pointed at when it was last indexed.  Only commits since then
+/// need indexing.
+pub struct GitRepository;
+
+#[derive(TypedBuilder)]
+pub struct CreateGitRepositoryProps {
+    pub path: PathBuf,
+    pub last_commit: String,
+}
+
+impl GitRepository {
+    /// Returns the last commit indexed for a repository, if it ever was.
+    pub async fn find_last_commit(
+        context: &context::Context,
+        path: PathBuf,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(GitRepositoryTable::LastCommit)
+            .from(GitRepositoryTable::Table)
+            .and_where(Expr::col(GitRepositoryTable::Path).eq(FilePath::new(path)))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let row: Option<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&context.db)
+            .await?;
+        Ok(row.map(|(last_commit,)| last_commit))
+    }
+
+    /// Records the last commit indexed for a repository.
+    pub async fn create(
+        context: &context::Context,
+        props: CreateGitRepositoryProps,
+    ) -> Result<(), sqlx::Error> {
+        let (query, values) = Query::insert()
+            .into_table(GitRepositoryTable::Table)
+            .columns([GitRepositoryTable::Path, GitRepositoryTable::LastCommit])
+            .values_panic([FilePath::new(props.path).into(), props.last_commit.into()])
+            .on_conflict(
+                OnConflict::column(GitRepositoryTable::Path)
+                    .update_column(GitRepositoryTable::LastCommit)
+                    .to_owned(),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .execute(&context.db)
+            .await?;
+        Ok(())
+    }
+}
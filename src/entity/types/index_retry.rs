@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use sea_query::{Asterisk, Expr, Iden, OnConflict, Order, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+
+use crate::{context, entity::columns::FilePath};
+
+use super::file::Excluded;
+
+#[derive(Iden)]
+pub enum IndexRetryTable {
+    #[iden = "index_retry"]
+    Table,
+    Path,
+    Attempts,
+    Error,
+    FailedAt,
+}
+
+/// A file that failed to index, such as when the embedding server was down,
+/// and is waiting to be indexed again.
+#[derive(sqlx::FromRow, Debug)]
+pub struct IndexRetry {
+    pub path: FilePath,
+
+    /// How many times in a row indexing the file has failed.
+    pub attempts: i64,
+
+    /// Why it last failed.
+    pub error: String,
+    pub failed_at: String,
+}
+
+impl IndexRetry {
+    /// Queues files to be indexed again, counting another attempt for those
+    /// already queued.
+    pub async fn create_many(
+        context: &context::Context,
+        paths: Vec<PathBuf>,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = Query::insert();
+
+        builder
+            .into_table(IndexRetryTable::Table)
+            .columns([IndexRetryTable::Path, IndexRetryTable::Error])
+            .on_conflict(
+                OnConflict::column(IndexRetryTable::Path)
+                    .values([
+                        (
+                            IndexRetryTable::Attempts,
+                            Expr::col((IndexRetryTable::Table, IndexRetryTable::Attempts)).add(1),
+                        ),
+                        (
+                            IndexRetryTable::Error,
+                            Expr::col((Excluded::Table, IndexRetryTable::Error)).into(),
+                        ),
+                        (IndexRetryTable::FailedAt, Expr::current_timestamp().into()),
+                    ])
+                    .to_owned(),
+            );
+
+        for path in paths {
+            builder.values_panic([FilePath::new(path).into(), error.into()]);
+        }
+
+        let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .execute(&context.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns every queued file, oldest failure first.
+    pub async fn find_all(context: &context::Context) -> Result<Vec<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(IndexRetryTable::Table)
+            .order_by(IndexRetryTable::FailedAt, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+
+    /// Takes files off the queue, once indexed or gone.
+    pub async fn delete_many(
+        context: &context::Context,
+        paths: Vec<PathBuf>,
+    ) -> Result<(), sqlx::Error> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let (query, values) = Query::delete()
+            .from_table(IndexRetryTable::Table)
+            .and_where(Expr::col(IndexRetryTable::Path).is_in(paths.into_iter().map(FilePath::new)))
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .execute(&context.db)
+            .await?;
+        Ok(())
+    }
+}
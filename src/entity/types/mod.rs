@@ -1,2 +1,9 @@
+pub mod embedding_cache;
+pub mod embedding_index;
 pub mod file;
 pub mod file_embedding;
+pub mod file_link;
+pub mod git_repository;
+pub mod index_retry;
+pub mod org_node;
+pub mod reembedding;
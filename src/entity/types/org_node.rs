@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use sea_query::{Asterisk, Expr, Iden, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::SqliteConnection;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    context,
+    entity::{columns::FilePath, Entity},
+};
+
+#[derive(Iden)]
+pub enum OrgNodeTable {
+    #[iden = "org_node"]
+    Table,
+    Id,
+    FilePath,
+    Line,
+    Title,
+}
+
+/// An Org node, i.e. a heading or file with an `:ID:` property, which
+/// `id:` links resolve to.
+#[derive(sqlx::FromRow, Debug)]
+pub struct OrgNode {
+    pub id: String,
+    pub file_path: FilePath,
+
+    /// The line of the node's heading, or `None` if the node is the whole
+    /// file.
+    pub line: Option<i64>,
+    pub title: Option<String>,
+}
+
+#[async_trait]
+impl Entity for OrgNode {
+    type ID = String;
+
+    fn get_id(&self) -> Self::ID {
+        self.id.to_string()
+    }
+
+    fn name() -> &'static str {
+        "org_node"
+    }
+
+    async fn find_many(
+        context: &context::Context,
+        ids: &[Self::ID],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(OrgNodeTable::Table)
+            .and_where(Expr::col(OrgNodeTable::Id).is_in(ids))
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+}
+
+#[derive(TypedBuilder)]
+pub struct CreateOrgNodeProps {
+    pub id: String,
+    pub file_path: PathBuf,
+    pub line: Option<usize>,
+    pub title: Option<String>,
+}
+
+impl OrgNode {
+    pub async fn create_many(
+        conn: &mut SqliteConnection,
+        nodes: Vec<CreateOrgNodeProps>,
+    ) -> Result<(), sqlx::Error> {
+        if nodes.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = Query::insert();
+
+        builder.into_table(OrgNodeTable::Table).columns([
+            OrgNodeTable::Id,
+            OrgNodeTable::FilePath,
+            OrgNodeTable::Line,
+            OrgNodeTable::Title,
+        ]);
+
+        for node in nodes {
+            builder.values_panic([
+                node.id.into(),
+                FilePath::new(node.file_path).into(),
+                node.line.map(|x| x as i64).into(),
+                node.title.into(),
+            ]);
+        }
+
+        let (query, values) = builder.build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use anyhow::Context as _;
+use sea_query::{
+    Alias, Asterisk, Expr, Func, Iden, Order, Query, SelectStatement, SimpleExpr,
+    SqliteQueryBuilder,
+};
+use sea_query_binder::SqlxBinder;
+use serde_json::json;
+use sqlx::SqliteConnection;
+
+use crate::context;
+
+use super::{
+    embedding_index::{create_vss_table, point_triggers_at, vss_table_name, EmbeddingIndex},
+    file_embedding::FileEmbeddingTable,
+};
+
+#[derive(Iden)]
+pub enum ReembeddingTable {
+    #[iden = "reembedding"]
+    Table,
+    Id,
+    Model,
+    Dimension,
+    Generation,
+}
+
+#[derive(Iden)]
+pub enum ReembeddingVectorTable {
+    #[iden = "reembedding_vector"]
+    Table,
+    FileEmbeddingId,
+    Embedding,
+}
+
+/// A rebuild of the vector index with another model.  New vectors go into a
+/// vss table of their own while the old one is still searched, and are
+/// swapped in once every fragment has one.
+#[derive(sqlx::FromRow, Debug)]
+pub struct Reembedding {
+    pub model: String,
+    pub dimension: i64,
+    pub generation: i64,
+}
+
+/// A fragment still to be re-embedded, by the rowid of its `file_embeddings`
+/// row.
+#[derive(sqlx::FromRow, Debug)]
+pub struct PendingFragment {
+    pub id: i64,
+    pub contents: String,
+}
+
+/// The trigger that drops the new vectors of deleted fragments.
+const DELETE_TRIGGER: &str = "delete_reembedding_vector";
+
+/// Drops a fragment's new vector, from staging and the vss table `table`,
+/// when the fragment is deleted.  SQLite reuses the rowids of deleted rows,
+/// so a fragment indexed later could otherwise pass for re-embedded.
+async fn create_delete_trigger(
+    conn: &mut SqliteConnection,
+    table: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        r#"CREATE TRIGGER {DELETE_TRIGGER}
+        AFTER DELETE ON file_embeddings
+        FOR EACH ROW
+        BEGIN
+            DELETE FROM reembedding_vector
+            WHERE file_embedding_id = OLD.rowid;
+
+            DELETE FROM {table}
+            WHERE rowid = OLD.rowid;
+        END"#
+    ))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+fn rowid() -> Alias {
+    Alias::new("rowid")
+}
+
+fn sub_query(select: SelectStatement) -> SimpleExpr {
+    SimpleExpr::SubQuery(None, Box::new(select.into_sub_query_statement()))
+}
+
+impl Reembedding {
+    pub async fn find(context: &context::Context) -> Result<Option<Self>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .column(Asterisk)
+            .from(ReembeddingTable::Table)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_optional(&context.db)
+            .await
+    }
+
+    /// The vss table the new vectors are written to.
+    pub fn vss_table(&self) -> String {
+        vss_table_name(self.generation)
+    }
+
+    /// Picks up where an interrupted rebuild with `model` left off, or
+    /// starts a new one, throwing away any rebuild with another model.
+    pub async fn start(
+        context: &context::Context,
+        model: &str,
+        dimension: usize,
+    ) -> anyhow::Result<Self> {
+        let stale = match Self::find(context).await? {
+            Some(current) if current.model == model && current.dimension == dimension as i64 => {
+                return Ok(current)
+            }
+            current => current,
+        };
+
+        let index = EmbeddingIndex::find(context)
+            .await?
+            .context("The embedding index has not been set up.")?;
+
+        let mut transaction = context.db.begin().await?;
+
+        if let Some(stale) = stale {
+            sqlx::query(&format!("DROP TRIGGER IF EXISTS {DELETE_TRIGGER}"))
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query(&format!("DROP TABLE IF EXISTS {}", stale.vss_table()))
+                .execute(&mut *transaction)
+                .await?;
+        }
+        for table in [
+            ReembeddingVectorTable::Table.to_string(),
+            ReembeddingTable::Table.to_string(),
+        ] {
+            sqlx::query(&format!("DELETE FROM {table}"))
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        let reembedding = Reembedding {
+            model: model.to_string(),
+            dimension: dimension as i64,
+            generation: index.generation + 1,
+        };
+        create_vss_table(
+            &mut transaction,
+            &reembedding.vss_table(),
+            reembedding.dimension,
+        )
+        .await?;
+        create_delete_trigger(&mut transaction, &reembedding.vss_table()).await?;
+
+        let (sql, values) = Query::insert()
+            .into_table(ReembeddingTable::Table)
+            .columns([
+                ReembeddingTable::Id,
+                ReembeddingTable::Model,
+                ReembeddingTable::Dimension,
+                ReembeddingTable::Generation,
+            ])
+            .values_panic([
+                1.into(),
+                reembedding.model.clone().into(),
+                reembedding.dimension.into(),
+                reembedding.generation.into(),
+            ])
+            .build_sqlx(SqliteQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(reembedding)
+    }
+
+    /// Returns the number of fragments re-embedded so far, and the number
+    /// there are.
+    pub async fn progress(&self, context: &context::Context) -> Result<(i64, i64), sqlx::Error> {
+        let (sql, values) = Query::select()
+            .expr(sub_query(
+                Query::select()
+                    .expr(Func::count(Expr::col(Asterisk)))
+                    .from(ReembeddingVectorTable::Table)
+                    .to_owned(),
+            ))
+            .expr(sub_query(
+                Query::select()
+                    .expr(Func::count(Expr::col(Asterisk)))
+                    .from(FileEmbeddingTable::Table)
+                    .to_owned(),
+            ))
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_one(&context.db)
+            .await
+    }
+
+    /// Returns up to `limit` of the fragments without a new vector yet.
+    pub async fn find_pending(
+        &self,
+        context: &context::Context,
+        limit: u64,
+    ) -> Result<Vec<PendingFragment>, sqlx::Error> {
+        let (sql, values) = Query::select()
+            .expr_as(Expr::col(rowid()), Alias::new("id"))
+            .column(FileEmbeddingTable::Contents)
+            .from(FileEmbeddingTable::Table)
+            .and_where(
+                Expr::col(rowid()).not_in_subquery(
+                    Query::select()
+                        .column(ReembeddingVectorTable::FileEmbeddingId)
+                        .from(ReembeddingVectorTable::Table)
+                        .to_owned(),
+                ),
+            )
+            .order_by(rowid(), Order::Asc)
+            .limit(limit)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&context.db)
+            .await
+    }
+
+    /// Stores the new vectors of fragments, by the rowid of their
+    /// `file_embeddings` row.
+    pub async fn create_many(
+        &self,
+        context: &context::Context,
+        vectors: Vec<(i64, Vec<f32>)>,
+    ) -> Result<(), sqlx::Error> {
+        if vectors.is_empty() {
+            return Ok(());
+        }
+
+        let mut staged = Query::insert();
+        staged.into_table(ReembeddingVectorTable::Table).columns([
+            ReembeddingVectorTable::FileEmbeddingId,
+            ReembeddingVectorTable::Embedding,
+        ]);
+
+        let mut indexed = Query::insert();
+        indexed
+            .into_table(Alias::new(self.vss_table()))
+            .columns([rowid(), Alias::new("embedding")]);
+
+        for (id, embedding) in vectors {
+            staged.values_panic([id.into(), json!(embedding).into()]);
+            indexed.values_panic([id.into(), json!(embedding).into()]);
+        }
+
+        // Both are written together, so a rebuild that is interrupted can
+        // trust that every vector staged is also in the vss table.
+        let mut transaction = context.db.begin().await?;
+        for builder in [staged, indexed] {
+            let (sql, values) = builder.build_sqlx(SqliteQueryBuilder);
+            sqlx::query_with(&sql, values)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Swaps the new vectors in for the old, in one transaction, so searches
+    /// see either index whole.  Returns `false`, changing nothing, if
+    /// fragments were indexed since the last batch, which need re-embedding
+    /// first.
+    pub async fn finish(&self, context: &context::Context) -> anyhow::Result<bool> {
+        let index = EmbeddingIndex::find(context)
+            .await?
+            .context("The embedding index has not been set up.")?;
+
+        let mut transaction = context.db.begin().await?;
+
+        // Writing first locks out the indexer, so no fragments can be added
+        // between counting them and swapping.  The new vectors are about to
+        // be the index, which the index's own triggers keep up.
+        sqlx::query(&format!("DROP TRIGGER IF EXISTS {DELETE_TRIGGER}"))
+            .execute(&mut *transaction)
+            .await?;
+
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Asterisk)))
+            .from(FileEmbeddingTable::Table)
+            .and_where(
+                Expr::col(rowid()).not_in_subquery(
+                    Query::select()
+                        .column(ReembeddingVectorTable::FileEmbeddingId)
+                        .from(ReembeddingVectorTable::Table)
+                        .to_owned(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+        let (pending,): (i64,) = sqlx::query_as_with(&sql, values)
+            .fetch_one(&mut *transaction)
+            .await?;
+        if pending > 0 {
+            transaction.rollback().await?;
+            return Ok(false);
+        }
+
+        let (sql, values) = Query::update()
+            .table(FileEmbeddingTable::Table)
+            .value(
+                FileEmbeddingTable::Embedding,
+                sub_query(
+                    Query::select()
+                        .column(ReembeddingVectorTable::Embedding)
+                        .from(ReembeddingVectorTable::Table)
+                        .and_where(
+                            Expr::col(ReembeddingVectorTable::FileEmbeddingId)
+                                .equals((FileEmbeddingTable::Table, rowid())),
+                        )
+                        .to_owned(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&mut *transaction)
+            .await?;
+
+        point_triggers_at(&mut transaction, &self.vss_table()).await?;
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", index.vss_table()))
+            .execute(&mut *transaction)
+            .await?;
+        EmbeddingIndex::update(
+            &mut transaction,
+            &self.model,
+            self.dimension,
+            self.generation,
+        )
+        .await?;
+
+        for table in [
+            ReembeddingVectorTable::Table.to_string(),
+            ReembeddingTable::Table.to_string(),
+        ] {
+            sqlx::query(&format!("DELETE FROM {table}"))
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+}
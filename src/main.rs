@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use commands::{ai, embeddings, indexer, search, Executor};
+use commands::{ai, embeddings, indexer, links, search, Executor};
 use platform::{init_db, init_project_dirs};
 
 mod commands;
@@ -13,7 +13,8 @@ mod config;
 enum Commands {
     Indexer(indexer::Indexer),
     Embeddings(embeddings::Embeddings),
-    Search(search::Search),
+    Search(Box<search::Search>),
+    Links(links::Links),
     AI(ai::AI),
 }
 
@@ -33,6 +34,7 @@ async fn main() -> anyhow::Result<()> {
         Commands::Indexer(indexer) => indexer.execute().await,
         Commands::Embeddings(embeddings) => embeddings.execute().await,
         Commands::Search(search) => search.execute().await,
+        Commands::Links(links) => links.execute().await,
         Commands::AI(ai) => ai.execute().await,
     }
 }
@@ -24,7 +24,7 @@ pub struct AIService {
 
 impl AIService {
     pub async fn try_new() -> anyhow::Result<Self> {
-        let config = Config::load().await;
+        let config = Config::load().await?;
         let api = ApiBuilder::new()
             .with_token(config.huggingface_token)
             .with_cache_dir(cache_dir())
@@ -1,95 +1,449 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, sync::Arc};
 
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use dataloader::{non_cached::Loader, BatchFn};
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
-use once_cell::sync::Lazy;
-use regex::Regex;
+use fastembed::{
+    EmbeddingModel, InitOptions, InitOptionsUserDefined, TextEmbedding, TokenizerFiles,
+    UserDefinedEmbeddingModel,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
-static HEADING_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\*+\s+(.+)$").unwrap());
+use crate::{
+    config::{Config, HttpEmbeddingModel, LocalEmbeddingModel, Pooling},
+    context,
+    entity::types::{embedding_index::EmbeddingIndex, reembedding::Reembedding},
+};
 
-struct EmbeddingBatchFn(TextEmbedding);
+use super::http_embedder::HttpEmbedder;
 
-impl BatchFn<String, Vec<f32>> for EmbeddingBatchFn {
-    async fn load(&mut self, keys: &[String]) -> HashMap<String, Vec<f32>> {
-        let results = self
-            .0
-            .embed(keys.to_vec(), Some(keys.len()))
-            .unwrap_or_default();
+/// Turns texts into vectors, one for each text and in the same order.
+pub trait Embedder: Send + Sync {
+    fn embed(&self, texts: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>>;
+}
+
+/// Embeds in-process with fastembed.
+struct FastembedEmbedder(TextEmbedding);
 
-        keys.iter()
-            .zip(results)
-            .map(|(key, emb)| (key.to_owned(), emb.to_owned()))
-            .collect::<HashMap<_, _>>()
+impl Embedder for FastembedEmbedder {
+    fn embed(&self, texts: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+        let batch_size = texts.len();
+        self.0.embed(texts, Some(batch_size))
     }
 }
 
-pub struct EmbeddingsService {
-    loader: Loader<String, Vec<f32>, EmbeddingBatchFn>,
+/// A text's embedding, or why its batch failed.  The loader can only hand
+/// back a value for each key, so a failed batch gives each of its texts the
+/// error.
+type EmbeddingResult = Result<Vec<f32>, String>;
+
+struct EmbeddingBatchFn(Arc<dyn Embedder>);
+
+impl BatchFn<String, EmbeddingResult> for EmbeddingBatchFn {
+    async fn load(&mut self, keys: &[String]) -> HashMap<String, EmbeddingResult> {
+        // Embedding runs a model or waits on a server, either of which would
+        // hold up the other tasks on this thread.
+        let embedder = self.0.clone();
+        let texts = keys.to_vec();
+        let results = tokio::task::spawn_blocking(move || embedder.embed(texts))
+            .await
+            .unwrap_or_else(|error| Err(error.into()));
+
+        let results = match results {
+            Ok(results) if results.len() == keys.len() => results.into_iter().map(Ok).collect(),
+            Ok(results) => {
+                let error = format!("Got {} embeddings for {} texts", results.len(), keys.len());
+                vec![Err(error); keys.len()]
+            }
+            Err(error) => vec![Err(format!("{:#}", error)); keys.len()],
+        };
+
+        keys.iter().cloned().zip(results).collect::<HashMap<_, _>>()
+    }
+}
+
+/// The model used when none is configured.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "BAAI/bge-small-en-v1.5";
+
+/// An embedding model, and how it wants text formatted.  Retrieval models
+/// are often trained asymmetrically, with queries marked differently from the
+/// passages they should find, and embed worse when used without the marks.
+struct ModelSpec {
+    /// Identifies the model vectors came from, so cached embeddings from one
+    /// model are never handed out for another.  Changing `passage_prefix`
+    /// changes the vectors too, so it needs a new ID.
+    id: &'static str,
+    model: EmbeddingModel,
+
+    /// The length of the model's vectors, which the vector index is built
+    /// for.
+    dimension: usize,
+    query_prefix: &'static str,
+    passage_prefix: &'static str,
+}
+
+/// BGE models want an instruction before queries, and passages as they are.
+const BGE_QUERY_PREFIX: &str = "Represent this sentence for searching relevant passages: ";
+
+/// The models that can be configured, by their Hugging Face name.
+const MODELS: &[ModelSpec] = &[
+    ModelSpec {
+        id: "BAAI/bge-small-en-v1.5",
+        model: EmbeddingModel::BGESmallENV15,
+        dimension: 384,
+        query_prefix: BGE_QUERY_PREFIX,
+        passage_prefix: "",
+    },
+    ModelSpec {
+        id: "BAAI/bge-base-en-v1.5",
+        model: EmbeddingModel::BGEBaseENV15,
+        dimension: 768,
+        query_prefix: BGE_QUERY_PREFIX,
+        passage_prefix: "",
+    },
+    ModelSpec {
+        id: "BAAI/bge-large-en-v1.5",
+        model: EmbeddingModel::BGELargeENV15,
+        dimension: 1024,
+        query_prefix: BGE_QUERY_PREFIX,
+        passage_prefix: "",
+    },
+    ModelSpec {
+        id: "sentence-transformers/all-MiniLM-L6-v2",
+        model: EmbeddingModel::AllMiniLML6V2,
+        dimension: 384,
+        query_prefix: "",
+        passage_prefix: "",
+    },
+    ModelSpec {
+        id: "nomic-ai/nomic-embed-text-v1.5",
+        model: EmbeddingModel::NomicEmbedTextV15,
+        dimension: 768,
+        query_prefix: "search_query: ",
+        passage_prefix: "search_document: ",
+    },
+    ModelSpec {
+        id: "intfloat/multilingual-e5-small",
+        model: EmbeddingModel::MultilingualE5Small,
+        dimension: 384,
+        query_prefix: "query: ",
+        passage_prefix: "passage: ",
+    },
+    ModelSpec {
+        id: "intfloat/multilingual-e5-base",
+        model: EmbeddingModel::MultilingualE5Base,
+        dimension: 768,
+        query_prefix: "query: ",
+        passage_prefix: "passage: ",
+    },
+    ModelSpec {
+        id: "intfloat/multilingual-e5-large",
+        model: EmbeddingModel::MultilingualE5Large,
+        dimension: 1024,
+        query_prefix: "query: ",
+        passage_prefix: "passage: ",
+    },
+];
+
+/// Learns the length of an embedder's vectors by embedding a word, which is
+/// surer than reading it from a model's settings.
+fn probe_dimension(embedder: &dyn Embedder) -> anyhow::Result<usize> {
+    embedder
+        .embed(vec!["dimension".to_string()])?
+        .first()
+        .map(|x| x.len())
+        .context("The model returned no embedding.")
+}
+
+/// The settings a sentence-transformers model keeps next to its weights, of
+/// which only pooling matters here.
+#[derive(Deserialize)]
+struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+
+    #[serde(default)]
+    pooling_mode_mean_tokens: bool,
+}
+
+/// The settings of a sentence-transformers model read when loading it.
+#[derive(Deserialize)]
+struct SentenceBertConfig {
+    max_seq_length: Option<usize>,
 }
 
-#[derive(Debug)]
-pub enum FileFragment {
-    Heading(String),
-    Paragraph(String),
+pub struct EmbeddingsService {
+    model_id: String,
+    dimension: usize,
+    query_prefix: String,
+    passage_prefix: String,
+    loader: Loader<String, EmbeddingResult, EmbeddingBatchFn>,
 }
 
 impl EmbeddingsService {
-    pub fn try_new() -> anyhow::Result<Self> {
+    /// Loads the model set in the config: a local one or an embedding
+    /// server if one is set, otherwise `embedding_model`.
+    pub async fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let local = config.local_embedding_model.clone();
+        let http = config.http_embedding_model.clone();
+        let model_id = config
+            .embedding_model
+            .as_deref()
+            .unwrap_or(DEFAULT_EMBEDDING_MODEL)
+            .to_string();
+
+        // Loading reads, or downloads, the model and embeds with it, all of
+        // which blocks.
+        tokio::task::spawn_blocking(move || match (local, http) {
+            (Some(_), Some(_)) => bail!(
+                "Set either `local_embedding_model` or `http_embedding_model` in \
+                 `.indexer.toml`, not both."
+            ),
+            (Some(local), None) => Self::try_new_local(&local),
+            (None, Some(http)) => Self::try_new_http(&http),
+            (None, None) => Self::try_new(&model_id),
+        })
+        .await?
+    }
+
+    /// Loads the model the vector index is searched with.  That's the one
+    /// set in the config, except while `indexer reembed` rebuilds the index
+    /// with it, when it's still the model the old vectors came from.  Only
+    /// built-in models can be loaded by name alone, so a rebuild away from a
+    /// local model or an embedding server has to finish first.
+    pub async fn for_index(context: &context::Context, config: &Config) -> anyhow::Result<Self> {
+        let (Some(reembedding), Some(index)) = (
+            Reembedding::find(context).await?,
+            EmbeddingIndex::find(context).await?,
+        ) else {
+            return Self::from_config(config).await;
+        };
+
+        if MODELS.iter().any(|x| x.id == index.model) {
+            return tokio::task::spawn_blocking(move || Self::try_new(&index.model)).await?;
+        }
+
+        let embeddings = Self::from_config(config).await?;
+        if embeddings.model_id() != index.model {
+            bail!(
+                "The index is being rebuilt with `{}`, and its old model `{}` can't be \
+                 loaded by name.  Finish `indexer reembed` first.",
+                reembedding.model,
+                index.model
+            );
+        }
+        Ok(embeddings)
+    }
+
+    /// Loads an embedding model by its Hugging Face name, e.g.
+    /// `BAAI/bge-small-en-v1.5`.
+    pub fn try_new(model_id: &str) -> anyhow::Result<Self> {
+        let spec = MODELS.iter().find(|x| x.id == model_id).ok_or_else(|| {
+            anyhow!(
+                "Unsupported embedding model `{model_id}`, expected one of: {}",
+                MODELS.iter().map(|x| x.id).collect::<Vec<_>>().join(", ")
+            )
+        })?;
+
         let model = TextEmbedding::try_new(InitOptions {
-            model_name: EmbeddingModel::BGESmallENV15,
+            model_name: spec.model.clone(),
             show_download_progress: true,
             ..Default::default()
         })?;
 
-        let batch_fn = EmbeddingBatchFn(model);
-        Ok(EmbeddingsService {
-            loader: Loader::new(batch_fn).with_max_batch_size(256),
-        })
+        Ok(Self::new(
+            Box::new(FastembedEmbedder(model)),
+            spec.id.to_string(),
+            spec.dimension,
+            spec.query_prefix.to_string(),
+            spec.passage_prefix.to_string(),
+        ))
     }
 
-    pub async fn parse_file(&self, file: &PathBuf) -> anyhow::Result<Vec<FileFragment>> {
-        let file_contents = tokio::fs::read_to_string(&file).await?;
+    /// Loads a model exported from sentence-transformers to ONNX, from a
+    /// directory holding `model.onnx` (or `onnx/model.onnx`) and its tokenizer
+    /// files.  Fragments are embedded from the first token's output and
+    /// normalized, so models pooled or scaled any other way are refused.
+    pub fn try_new_local(local: &LocalEmbeddingModel) -> anyhow::Result<Self> {
+        let dir = &local.path;
+        let read = |name: &str| {
+            std::fs::read(dir.join(name))
+                .with_context(|| format!("Could not read {} in {}", name, dir.display()))
+        };
 
-        Ok(file_contents
-            .lines()
-            .map(|line| {
-                if let Some(captures) = HEADING_REGEX.captures(line) {
-                    FileFragment::Heading(captures[0].to_string())
-                } else {
-                    FileFragment::Paragraph(line.to_string())
-                }
-            })
-            .fold(vec![], |mut acc, x| {
-                if let Some(FileFragment::Paragraph(last_contents)) = acc.last_mut() {
-                    if let FileFragment::Paragraph(contents) = x {
-                        last_contents.push('\n');
-                        last_contents.push_str(&contents);
-                    } else {
-                        acc.push(x);
-                    }
-                } else {
-                    acc.push(x);
+        let pooling = match local.pooling {
+            Some(pooling) => pooling,
+            None => {
+                let pooling = read("1_Pooling/config.json").context(
+                    "Set the model's `pooling` in `.indexer.toml`, as it doesn't say itself.",
+                )?;
+                let pooling: PoolingConfig = serde_json::from_slice(&pooling)
+                    .context("Could not parse 1_Pooling/config.json")?;
+                match (
+                    pooling.pooling_mode_cls_token,
+                    pooling.pooling_mode_mean_tokens,
+                ) {
+                    (true, false) => Pooling::Cls,
+                    (false, true) => Pooling::Mean,
+                    _ => bail!(
+                        "{} is pooled neither from its CLS token nor by the mean of its tokens.",
+                        dir.display()
+                    ),
                 }
+            }
+        };
+        if pooling != Pooling::Cls {
+            bail!(
+                "{} is pooled by the mean of its tokens, but only `cls` pooling is supported.",
+                dir.display()
+            );
+        }
+        if local.normalize == Some(false) {
+            bail!(
+                "{} has `normalize = false`, but vectors are always normalized.",
+                dir.display()
+            );
+        }
+
+        let max_length = match std::fs::read(dir.join("sentence_bert_config.json")) {
+            Ok(settings) => {
+                serde_json::from_slice::<SentenceBertConfig>(&settings)
+                    .context("Could not parse sentence_bert_config.json")?
+                    .max_seq_length
+            }
+            Err(_) => None,
+        };
+
+        let config_file = read("config.json")?;
+        let onnx_file = read("model.onnx").or_else(|_| read("onnx/model.onnx"))?;
+
+        // Identified by its weights, so retraining the model in place never
+        // reuses vectors cached for the old one.
+        let mut hasher = Sha256::new();
+        hasher.update(&onnx_file);
+        hasher.update(local.passage_prefix.as_bytes());
+        let model_id = format!(
+            "local/{}@{}",
+            dir.file_name().unwrap_or_default().to_string_lossy(),
+            hex::encode(&hasher.finalize()[..8])
+        );
+
+        let model = TextEmbedding::try_new_from_user_defined(
+            UserDefinedEmbeddingModel {
+                onnx_file,
+                tokenizer_files: TokenizerFiles {
+                    tokenizer_file: read("tokenizer.json")?,
+                    config_file,
+                    special_tokens_map_file: read("special_tokens_map.json")?,
+                    tokenizer_config_file: read("tokenizer_config.json")?,
+                },
+            },
+            match max_length {
+                Some(max_length) => InitOptionsUserDefined {
+                    max_length,
+                    ..Default::default()
+                },
+                None => Default::default(),
+            },
+        )?;
+
+        let embedder = FastembedEmbedder(model);
+        let dimension = probe_dimension(&embedder)?;
 
-                acc
-            }))
+        Ok(Self::new(
+            Box::new(embedder),
+            model_id,
+            dimension,
+            local.query_prefix.to_string(),
+            local.passage_prefix.to_string(),
+        ))
     }
 
+    /// Embeds with a server speaking the OpenAI `/v1/embeddings` format,
+    /// such as Ollama or text-embeddings-inference.  The server is asked
+    /// for an embedding up front, both to check it's there and to learn the
+    /// model's dimension.
+    pub fn try_new_http(http: &HttpEmbeddingModel) -> anyhow::Result<Self> {
+        let embedder = HttpEmbedder::new(http);
+        let dimension = probe_dimension(&embedder)?;
+
+        // Vectors are only as stable as the name the server gives the model,
+        // and the prefix put before passages.
+        let model_id = match http.passage_prefix.is_empty() {
+            true => format!("http/{}", http.model),
+            false => format!("http/{}+{}", http.model, http.passage_prefix.trim()),
+        };
+
+        Ok(Self::new(
+            Box::new(embedder),
+            model_id,
+            dimension,
+            http.query_prefix.to_string(),
+            http.passage_prefix.to_string(),
+        ))
+    }
+
+    fn new(
+        embedder: Box<dyn Embedder>,
+        model_id: String,
+        dimension: usize,
+        query_prefix: String,
+        passage_prefix: String,
+    ) -> Self {
+        let batch_fn = EmbeddingBatchFn(Arc::from(embedder));
+        EmbeddingsService {
+            model_id,
+            dimension,
+            query_prefix,
+            passage_prefix,
+            loader: Loader::new(batch_fn).with_max_batch_size(256),
+        }
+    }
+
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Embeds passages to be searched, keyed by their text.  Fails if any of
+    /// them couldn't be embedded.
     pub async fn embeddings<'b>(
         &self,
         texts: &[String],
     ) -> anyhow::Result<HashMap<String, Vec<f32>>> {
-        self.loader
-            .try_load_many(texts.to_vec())
+        let embeddings = self
+            .loader
+            .try_load_many(
+                texts
+                    .iter()
+                    .map(|x| format!("{}{}", self.passage_prefix, x))
+                    .collect(),
+            )
             .await
-            .context("Could not load from dataloader.")
+            .context("Could not load from dataloader.")?;
+
+        texts
+            .iter()
+            .map(|x| {
+                let embedding = embeddings
+                    .get(&format!("{}{}", self.passage_prefix, x))
+                    .context("No embedding was returned.")?
+                    .clone()
+                    .map_err(|e| anyhow!("Could not embed texts: {e}"))?;
+                Ok((x.to_string(), embedding))
+            })
+            .collect()
     }
 
-    pub async fn embedding(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+    /// Embeds a search query, to be compared against embedded passages.
+    pub async fn query_embedding(&self, query: &str) -> anyhow::Result<Vec<f32>> {
         self.loader
-            .try_load(text.to_string())
+            .try_load(format!("{}{}", self.query_prefix, query))
             .await
-            .context("Could not load from dataloader.")
+            .context("Could not load from dataloader.")?
+            .map_err(|e| anyhow!("Could not embed query: {e}"))
     }
 }
@@ -18,6 +18,8 @@ use sha2::{Digest, Sha256};
 use std::io;
 use tokio::fs::DirEntry;
 
+use super::parsers::mail;
+
 #[async_recursion]
 /// Given a directory, will return all files under it.
 async fn visit_dirs(dir: &Path) -> anyhow::Result<Vec<DirEntry>> {
@@ -66,6 +68,23 @@ impl<'a> FilesService {
         Ok((path, sha256.finalize().to_vec()))
     }
 
+    /// Given a mail archive, returns the files holding its messages: an mbox
+    /// file itself, or every message in the Maildirs under a directory.
+    pub async fn read_mailbox(mailbox: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        if mailbox.is_file() {
+            return Ok(vec![mailbox.to_path_buf()]);
+        }
+
+        let messages = visit_dirs(mailbox)
+            .await?
+            .into_iter()
+            .map(|x| x.path())
+            .filter(|x| mail::is_maildir_message(x))
+            .collect();
+
+        Ok(messages)
+    }
+
     /// Given a list of files, will return hashes of each file result.
     pub fn hash_files<'b>(&self, files: &'b [PathBuf]) -> HashMap<&'b PathBuf, Vec<u8>> {
         // Just ensure everything passed is a file, to be defensive.
@@ -77,9 +96,11 @@ impl<'a> FilesService {
             .collect::<HashMap<_, _>>()
     }
 
+    /// Watches each of the given paths, recursively for directories, sending
+    /// batches of changes to the returned receiver.
     pub fn watch<P: AsRef<Path>>(
         &self,
-        path: P,
+        paths: &[P],
     ) -> notify::Result<(Debouncer<FsEventWatcher>, DebouncedEventReceiver)> {
         let (mut tx, rx) = channel(1);
 
@@ -90,9 +111,16 @@ impl<'a> FilesService {
             })
         })?;
 
-        debouncer
-            .watcher()
-            .watch(path.as_ref(), RecursiveMode::Recursive)?;
+        // A missing mailbox or repository shouldn't stop the other paths from
+        // being watched.
+        for path in paths {
+            let path = path.as_ref();
+            if !path.exists() {
+                println!("Not watching {}, it does not exist", path.display());
+                continue;
+            }
+            debouncer.watcher().watch(path, RecursiveMode::Recursive)?;
+        }
 
         Ok((debouncer, rx))
     }
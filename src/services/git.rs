@@ -0,0 +1,262 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat};
+use git2::{Commit, Oid, Patch, Repository, Sort};
+
+use super::parsers::{
+    lines_with_ranges, FileFragment, FragmentKind, FragmentRange, Metadata, ParsedFile,
+};
+
+/// Hunks longer than this are split into windows of at most this many lines.
+const MAX_HUNK_LINES: usize = 60;
+
+/// A commit, parsed into fragments like any other indexed document.
+pub struct CommitDocument {
+    /// Where the commit is stored: the repository's path followed by
+    /// `@<commit hash>`.
+    pub path: PathBuf,
+    pub hash: Vec<u8>,
+    pub parsed: ParsedFile,
+}
+
+/// The commits of a repository not yet indexed, parents before children,
+/// along with the commit they lead up to.
+pub struct NewCommits {
+    pub head: Option<String>,
+    pub ids: Vec<String>,
+}
+
+/// A service that reads the history of a local git repository.
+pub struct GitService {
+    repo_path: PathBuf,
+}
+
+impl GitService {
+    pub fn new(repo_path: PathBuf) -> GitService {
+        GitService { repo_path }
+    }
+
+    /// The path of the repository's git directory, which changes whenever a
+    /// commit is made.
+    pub fn git_dir(&self) -> anyhow::Result<PathBuf> {
+        Ok(Repository::open(&self.repo_path)?.path().to_path_buf())
+    }
+
+    /// Where a commit is stored: the repository's path followed by
+    /// `@<commit hash>`.
+    pub fn commit_path(&self, id: &str) -> PathBuf {
+        PathBuf::from(format!("{}@{}", self.repo_path.display(), id))
+    }
+
+    /// Lists the commits reachable from HEAD that aren't reachable from
+    /// `since`, the last commit indexed.  If `since` is no longer in the
+    /// repository (history was rewritten), every commit is listed again.
+    pub async fn commits_since(&self, since: Option<String>) -> anyhow::Result<NewCommits> {
+        let repo_path = self.repo_path.to_path_buf();
+
+        // libgit2 is blocking, and walking a long history takes a while.
+        tokio::task::spawn_blocking(move || list_commits(&repo_path, since.as_deref())).await?
+    }
+
+    /// Reads and parses the given commits.
+    pub async fn read_commits(&self, ids: Vec<String>) -> anyhow::Result<Vec<CommitDocument>> {
+        let repo_path = self.repo_path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let service = GitService::new(repo_path);
+            let repo = Repository::open(&service.repo_path)?;
+
+            ids.iter()
+                .map(|id| {
+                    let commit = repo.find_commit(Oid::from_str(id)?)?;
+                    Ok(CommitDocument {
+                        path: service.commit_path(id),
+                        hash: commit.id().as_bytes().to_vec(),
+                        parsed: parse_commit(&repo, &commit)?,
+                    })
+                })
+                .collect()
+        })
+        .await?
+    }
+}
+
+fn list_commits(repo_path: &Path, since: Option<&str>) -> anyhow::Result<NewCommits> {
+    let repo = Repository::open(repo_path)?;
+
+    // A repository without commits has no HEAD to walk from.
+    let Some(head) = repo.head().ok().and_then(|x| x.target()) else {
+        return Ok(NewCommits {
+            head: None,
+            ids: vec![],
+        });
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME | Sort::REVERSE)?;
+    revwalk.push(head)?;
+
+    if let Some(since) = since.and_then(|x| Oid::from_str(x).ok()) {
+        // Fails if the commit is gone, in which case everything is new.
+        let _ = revwalk.hide(since);
+    }
+
+    let ids = revwalk
+        .map(|x| Ok(x?.to_string()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(NewCommits {
+        head: Some(head.to_string()),
+        ids,
+    })
+}
+
+/// Parses a commit into fragments: its summary as a heading, the paragraphs
+/// of its message, and the hunks of its diff against its first parent.  Hunks
+/// are located by the file they touch and their lines in its new version,
+/// with byte ranges relative to the hunk itself.  The author, date, hash and
+/// touched paths are recorded as metadata.
+fn parse_commit(repo: &Repository, commit: &Commit) -> anyhow::Result<ParsedFile> {
+    let summary = commit.summary().unwrap_or_default().to_string();
+    let heading_path = vec![summary.to_string()];
+    let mut fragments = vec![];
+
+    let mut message_lines = lines_with_ranges(commit.message().unwrap_or_default());
+    if let Some((line, range)) = message_lines.next() {
+        fragments.push(FileFragment {
+            kind: FragmentKind::Heading,
+            contents: line.to_string(),
+            range,
+            heading_path: heading_path.clone(),
+            page: None,
+            chapter: None,
+            language: None,
+            cell: None,
+            metadata: Metadata::default(),
+        });
+    }
+
+    let mut current: Option<FileFragment> = None;
+    for (line, range) in message_lines {
+        if line.trim().is_empty() {
+            fragments.extend(current.take());
+            continue;
+        }
+
+        match current.as_mut() {
+            Some(fragment) => {
+                fragment.contents.push('\n');
+                fragment.contents.push_str(line);
+                fragment.range.end_line = range.end_line;
+                fragment.range.end_byte = range.end_byte;
+            }
+            None => {
+                current = Some(FileFragment {
+                    kind: FragmentKind::Paragraph,
+                    contents: line.to_string(),
+                    range,
+                    heading_path: heading_path.clone(),
+                    page: None,
+                    chapter: None,
+                    language: None,
+                    cell: None,
+                    metadata: Metadata::default(),
+                })
+            }
+        }
+    }
+    fragments.extend(current.take());
+
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+
+    let mut touched_paths = vec![];
+    for index in 0..diff.deltas().len() {
+        let Some(patch) = Patch::from_diff(&diff, index)? else {
+            continue;
+        };
+
+        let delta = patch.delta();
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        let path = path.display().to_string();
+        touched_paths.push(path.to_string());
+
+        if delta.flags().is_binary() {
+            continue;
+        }
+
+        for hunk_index in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(hunk_index)?;
+            let lines = (0..line_count)
+                .map(|x| patch.line_in_hunk(hunk_index, x))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Only context and added lines are in the new version of the
+            // file, so only they advance the line number.
+            let mut next_line = hunk.new_start() as usize;
+            for window in lines.chunks(MAX_HUNK_LINES) {
+                let contents = window
+                    .iter()
+                    .map(|x| {
+                        let content = String::from_utf8_lossy(x.content());
+                        format!("{}{}", x.origin(), content.trim_end_matches('\n'))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let new_lines = window
+                    .iter()
+                    .filter(|x| matches!(x.origin(), ' ' | '+'))
+                    .count();
+
+                fragments.push(FileFragment {
+                    kind: FragmentKind::Paragraph,
+                    range: FragmentRange {
+                        start_line: next_line,
+                        end_line: next_line + new_lines.saturating_sub(1),
+                        start_byte: 0,
+                        end_byte: contents.len(),
+                    },
+                    contents,
+                    heading_path: vec![summary.to_string(), path.to_string()],
+                    page: None,
+                    chapter: Some(path.to_string()),
+                    language: None,
+                    cell: None,
+                    metadata: Metadata::default(),
+                });
+                next_line += new_lines;
+            }
+        }
+    }
+
+    let author = commit.author();
+    let date = DateTime::from_timestamp(commit.time().seconds(), 0)
+        .map(|x| x.to_rfc3339_opts(SecondsFormat::Secs, true));
+
+    let mut parsed = ParsedFile::from(fragments);
+    let metadata = [
+        ("commit", Some(commit.id().to_string())),
+        ("subject", Some(summary)),
+        ("author", author.email().map(|x| x.to_string())),
+        ("author_name", author.name().map(|x| x.to_string())),
+        ("date", date),
+    ];
+    for (key, value) in metadata {
+        if let Some(value) = value.filter(|x| !x.is_empty()) {
+            parsed.metadata.insert(key, value);
+        }
+    }
+
+    // One value per path, so a path filter matches commits touching others
+    // too.
+    for path in touched_paths {
+        parsed.metadata.push("paths", path);
+    }
+
+    Ok(parsed)
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::HttpEmbeddingModel;
+
+use super::embeddings::Embedder;
+
+/// How many texts are sent in one request.  Servers cap this, e.g.
+/// text-embeddings-inference at 32 by default.
+const BATCH_SIZE: usize = 32;
+
+/// How long to wait for the server to embed a batch.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Embeds with a server speaking the OpenAI `/v1/embeddings` format.
+pub struct HttpEmbedder {
+    agent: ureq::Agent,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl HttpEmbedder {
+    pub fn new(config: &HttpEmbeddingModel) -> Self {
+        HttpEmbedder {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            url: config.url.to_string(),
+            model: config.model.to_string(),
+            api_key: config.api_key.clone(),
+        }
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut request = self.agent.post(&self.url);
+        if let Some(api_key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", api_key));
+        }
+
+        let response: EmbeddingResponse = request
+            .send_json(json!({ "model": self.model, "input": texts }))
+            .with_context(|| format!("Could not embed with {}", self.url))?
+            .into_json()
+            .context("Could not read the embedding server's response.")?;
+
+        if response.data.len() != texts.len() {
+            bail!(
+                "The embedding server returned {} embeddings for {} texts.",
+                response.data.len(),
+                texts.len()
+            );
+        }
+
+        // The order of the embeddings is given by their index, not by their
+        // position in the response.
+        let mut data = response.data;
+        data.sort_by_key(|x| x.index);
+        Ok(data.into_iter().map(|x| normalize(x.embedding)).collect())
+    }
+}
+
+/// Scales a vector to unit length, as fastembed does its own, since servers
+/// differ on whether they do.  The index ranks by L2 distance, which only
+/// orders like cosine similarity between unit vectors.
+fn normalize(vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector;
+    }
+    vector.into_iter().map(|x| x / norm).collect()
+}
+
+impl Embedder for HttpEmbedder {
+    fn embed(&self, texts: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            embeddings.extend(self.embed_batch(batch)?);
+        }
+        Ok(embeddings)
+    }
+}
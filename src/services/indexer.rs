@@ -1,98 +1,521 @@
-use std::{collections::HashMap, ops::Deref, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
+use anyhow::bail;
 use futures::stream::{self, StreamExt};
 
 use crate::{
     context::Context,
-    entity::types::{
-        file::{CreateFileProps, File},
-        file_embedding::{CreateFileEmbeddingProps, FileEmbedding},
+    entity::{
+        columns::FilePath,
+        types::{
+            embedding_cache::{CreateEmbeddingCacheProps, EmbeddingCache},
+            embedding_index::EmbeddingIndex,
+            file::{CreateFileProps, File},
+            file_embedding::{CreateFileEmbeddingProps, FileEmbedding},
+            file_link::{CreateFileLinkProps, FileLink},
+            git_repository::{CreateGitRepositoryProps, GitRepository},
+            index_retry::IndexRetry,
+            org_node::{CreateOrgNodeProps, OrgNode},
+            reembedding::Reembedding,
+        },
+        Entity,
     },
-    services::embeddings::FileFragment,
 };
 
-use super::{embeddings::EmbeddingsService, files::FilesService};
+use super::{
+    embeddings::EmbeddingsService,
+    files::FilesService,
+    git::GitService,
+    parsers::{self, ParseOptions, ParsedFile},
+};
+
+/// How many fragments are re-embedded, and stored, at a time.
+const REEMBED_BATCH_SIZE: u64 = 256;
+
+/// How many files, or commits, are read, parsed and stored at a time.
+const INDEX_PAGE_SIZE: usize = 100;
+
+/// A file, or commit, parsed and ready to store under its hash.
+struct ParsedDocument {
+    path: PathBuf,
+    hash: Vec<u8>,
+    parsed: ParsedFile,
+}
 
 pub struct IndexerService {
     embeddings: EmbeddingsService,
     files: FilesService,
+    parse_options: ParseOptions,
     context: Context,
 }
 
 impl IndexerService {
-    pub fn try_new(context: Context, root_dir: PathBuf) -> anyhow::Result<Self> {
-        let embeddings = EmbeddingsService::try_new()?;
-        Ok(IndexerService {
+    pub fn new(
+        context: Context,
+        root_dir: PathBuf,
+        parse_options: ParseOptions,
+        embeddings: EmbeddingsService,
+    ) -> Self {
+        IndexerService {
             context,
             embeddings,
             files: FilesService::new(root_dir),
-        })
+            parse_options,
+        }
     }
 
-    pub async fn index_files(&self, paths: &[PathBuf]) -> anyhow::Result<()> {
-        // Get file hashes for each path.
-        let hashes = self.files.hash_files(paths);
+    /// Makes sure the vector index is built for the configured model, before
+    /// anything is embedded into it.
+    pub async fn ensure_embedding_index(&self) -> anyhow::Result<()> {
+        EmbeddingIndex::ensure(
+            &self.context,
+            self.embeddings.model_id(),
+            self.embeddings.dimension(),
+        )
+        .await
+    }
+
+    /// Makes sure the index still holds vectors from the model this indexer
+    /// embeds with, which `indexer reembed` may have swapped out.
+    pub async fn check_embedding_model(&self) -> anyhow::Result<()> {
+        let mut conn = self.context.db.acquire().await?;
+        EmbeddingIndex::check_current(&mut conn, self.embeddings.model_id()).await
+    }
 
-        // Update file hashes in file table
-        let changed_paths = File::create_many(
+    /// Re-embeds every indexed fragment with the configured model, from the
+    /// contents stored with it, then swaps the new vectors in.  Searches use
+    /// the old vectors until then.  If interrupted, running it again with the
+    /// same model carries on where it stopped.
+    pub async fn reembed(&self) -> anyhow::Result<()> {
+        let reembedding = Reembedding::start(
             &self.context,
-            hashes
+            self.embeddings.model_id(),
+            self.embeddings.dimension(),
+        )
+        .await?;
+
+        loop {
+            let pending = reembedding
+                .find_pending(&self.context, REEMBED_BATCH_SIZE)
+                .await?;
+
+            if pending.is_empty() {
+                // Fragments indexed meanwhile are left to the next batch.
+                if reembedding.finish(&self.context).await? {
+                    break;
+                }
+                continue;
+            }
+
+            let texts = pending
+                .iter()
+                .map(|x| x.contents.to_string())
+                .collect::<Vec<_>>();
+            let embeddings = self.embed_with_cache(&texts).await?;
+
+            let vectors = pending
                 .into_iter()
-                .map(|(path, hash)| {
-                    CreateFileProps::builder()
-                        .path(path.to_path_buf())
-                        .hash(hash)
+                .filter_map(|x| Some((x.id, embeddings.get(&x.contents)?.to_owned())))
+                .collect::<Vec<_>>();
+            if vectors.is_empty() {
+                bail!("Could not embed fragments with {}.", reembedding.model);
+            }
+            reembedding.create_many(&self.context, vectors).await?;
+
+            let (done, total) = reembedding.progress(&self.context).await?;
+            println!("Re-embedded {done}/{total} fragments");
+        }
+
+        // Nothing is embedded with the old model any more.
+        EmbeddingCache::delete_other_models(&self.context, &reembedding.model).await?;
+
+        println!("Swapped in the index for {}", reembedding.model);
+        Ok(())
+    }
+
+    /// Embeds texts, reusing any vectors already in the embedding cache for
+    /// the current model and caching the ones that had to be computed.
+    async fn embed_with_cache(
+        &self,
+        texts: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<f32>>> {
+        let model = self.embeddings.model_id();
+        let mut embeddings_map =
+            EmbeddingCache::find_many_for_texts(&self.context, model, texts).await?;
+
+        let missing_texts = texts
+            .iter()
+            .filter(|x| !embeddings_map.contains_key(*x))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if missing_texts.is_empty() {
+            return Ok(embeddings_map);
+        }
+
+        let computed = self.embeddings.embeddings(&missing_texts[..]).await?;
+
+        EmbeddingCache::create_many(
+            &self.context,
+            model,
+            computed
+                .iter()
+                .map(|(text, embedding)| {
+                    CreateEmbeddingCacheProps::builder()
+                        .text(text.to_string())
+                        .embedding(embedding.to_owned())
                         .build()
                 })
                 .collect(),
         )
-        .await?
-        .into_iter()
-        .map(|x| x.path.0.to_path_buf())
-        .collect::<Vec<_>>();
+        .await?;
+
+        embeddings_map.extend(computed);
+        Ok(embeddings_map)
+    }
+
+    /// Drops everything indexed for files that have been removed, such as
+    /// Maildir messages moved to another folder.
+    pub async fn remove_files(&self, paths: &[PathBuf]) -> anyhow::Result<()> {
+        File::delete_many(&self.context, paths.to_vec()).await?;
+        IndexRetry::delete_many(&self.context, paths.to_vec()).await?;
+        EmbeddingCache::delete_unused(&self.context).await?;
+        Ok(())
+    }
 
-        if changed_paths.is_empty() {
+    /// Indexes again the files that failed to, such as while the embedding
+    /// server was down.  Files that have since gone are dropped from the
+    /// queue.
+    pub async fn retry_failed(&self) -> anyhow::Result<()> {
+        let (paths, removed): (Vec<_>, Vec<_>) = IndexRetry::find_all(&self.context)
+            .await?
+            .into_iter()
+            .map(|x| x.path.0)
+            .partition(|x| x.is_file());
+
+        IndexRetry::delete_many(&self.context, removed).await?;
+        if paths.is_empty() {
             return Ok(());
         }
 
-        // Get embeddings for each file
-        let fragments_to_index = stream::iter(changed_paths)
-            .filter_map(|x| async move {
-                match self.embeddings.parse_file(&x).await {
-                    Ok(parsed) => Some(async move { (x, parsed) }),
-                    Err(_) => None,
+        println!("Retrying {} files that failed to index", paths.len());
+        self.index_files(&paths).await
+    }
+
+    /// Indexes the files that changed since they were last indexed, a page at
+    /// a time.  Files that fail are queued to be retried, and the rest are
+    /// indexed regardless.
+    pub async fn index_files(&self, paths: &[PathBuf]) -> anyhow::Result<()> {
+        let mut failed = vec![];
+        for page in paths.chunks(INDEX_PAGE_SIZE) {
+            failed.extend(self.index_file_page(page).await?);
+        }
+
+        // The texts of fragments that were edited or removed aren't
+        // embedded again, so their cached embeddings go.
+        EmbeddingCache::delete_unused(&self.context).await?;
+
+        let Some((path, error)) = failed.first() else {
+            return Ok(());
+        };
+        bail!(
+            "{} files failed and will be retried, {} with: {:#}",
+            failed.len(),
+            path.display(),
+            error
+        );
+    }
+
+    async fn index_file_page(
+        &self,
+        paths: &[PathBuf],
+    ) -> anyhow::Result<Vec<(PathBuf, anyhow::Error)>> {
+        // Get file hashes for each path, including the options it is parsed
+        // with.
+        let hashes = self
+            .files
+            .hash_files(paths)
+            .into_iter()
+            .map(|(path, hash)| (path, self.parse_options.stamp_hash(path, hash)))
+            .collect::<HashMap<_, _>>();
+
+        // Only files whose hash differs from the recorded one have changed.
+        let ids = hashes
+            .keys()
+            .map(|x| FilePath::new(x.to_path_buf()))
+            .collect::<Vec<_>>();
+        let stored_hashes = File::find_many(&self.context, &ids)
+            .await?
+            .into_iter()
+            .map(|x| (x.path.0, x.hash))
+            .collect::<HashMap<_, _>>();
+
+        let changed = hashes
+            .into_iter()
+            .filter(|(path, hash)| stored_hashes.get(*path) != Some(hash))
+            .map(|(path, hash)| (path.to_path_buf(), hash))
+            .collect::<Vec<_>>();
+
+        if changed.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // Files that can't be parsed are queued like the ones that can't be
+        // stored, and their hashes left as they were, so they're parsed
+        // again rather than skipped until they change.
+        let parsed = stream::iter(changed)
+            .map(|(path, hash)| async move {
+                match parsers::parse_file(&path, &self.parse_options).await {
+                    Ok(parsed) => Ok(ParsedDocument { path, hash, parsed }),
+                    Err(error) => Err((path, error.context("Could not parse"))),
                 }
             })
-            .boxed()
             .buffer_unordered(10)
-            .collect::<HashMap<_, _>>()
+            .collect::<Vec<_>>()
             .await;
 
-        let embedding_texts = fragments_to_index
+        let mut documents = vec![];
+        let mut failed = vec![];
+        for result in parsed {
+            match result {
+                Ok(document) => documents.push(document),
+                Err(failure) => failed.push(failure),
+            }
+        }
+
+        let paths = documents
             .iter()
-            .flat_map(|(path, text)| {
-                text.iter().map(|x| match x {
-                    FileFragment::Heading(inside) => (inside.to_string(), path.to_path_buf()),
-                    FileFragment::Paragraph(inside) => (inside.to_string(), path.to_path_buf()),
-                })
-            })
-            .collect::<HashMap<_, _>>();
+            .map(|x| x.path.to_path_buf())
+            .collect::<Vec<_>>();
+        failed.extend(self.store_parsed(documents).await?);
 
-        let embeddings_map = self
-            .embeddings
-            .embeddings(&embedding_texts.keys().cloned().collect::<Vec<_>>()[..])
+        IndexRetry::delete_many(
+            &self.context,
+            paths
+                .into_iter()
+                .filter(|x| !failed.iter().any(|(path, _)| path == x))
+                .collect(),
+        )
+        .await?;
+        for (path, error) in &failed {
+            IndexRetry::create_many(
+                &self.context,
+                vec![path.to_path_buf()],
+                &format!("{:#}", error),
+            )
             .await?;
+        }
 
-        FileEmbedding::create_many(
+        Ok(failed)
+    }
+
+    /// Indexes the commits made to a git repository since it was last
+    /// indexed, a page at a time.  Each commit is stored like a file, under
+    /// the repository's path followed by `@<commit hash>`.
+    pub async fn index_repository(&self, repo_path: &Path) -> anyhow::Result<()> {
+        let last_commit =
+            GitRepository::find_last_commit(&self.context, repo_path.to_path_buf()).await?;
+
+        let git = GitService::new(repo_path.to_path_buf());
+        let new_commits = git.commits_since(last_commit.clone()).await?;
+
+        let Some(head) = new_commits.head else {
+            return Ok(());
+        };
+        if last_commit.as_ref() == Some(&head) {
+            return Ok(());
+        }
+
+        for page in new_commits.ids.chunks(INDEX_PAGE_SIZE) {
+            // Commits already indexed keep their hash, so only new ones are
+            // read and stored.
+            let paths = page
+                .iter()
+                .map(|x| FilePath::new(git.commit_path(x)))
+                .collect::<Vec<_>>();
+            let indexed = File::find_many(&self.context, &paths)
+                .await?
+                .into_iter()
+                .map(|x| x.path.0)
+                .collect::<HashSet<_>>();
+
+            let ids = page
+                .iter()
+                .filter(|x| !indexed.contains(&git.commit_path(x)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let documents = git
+                .read_commits(ids)
+                .await?
+                .into_iter()
+                .map(|x| ParsedDocument {
+                    path: x.path,
+                    hash: x.hash,
+                    parsed: x.parsed,
+                })
+                .collect::<Vec<_>>();
+
+            // The repository's last commit only moves on once its commits
+            // are stored, so the ones that failed are indexed again next
+            // time.
+            if let Some((path, error)) = self.store_parsed(documents).await?.pop() {
+                bail!("Could not index {}: {:#}", path.display(), error);
+            }
+
+            // Parents are walked before their children, so everything behind
+            // the page's last commit is indexed.  Commits of other branches
+            // are walked again next time, but come back as already indexed.
+            if let Some(last) = page.last() {
+                self.record_last_commit(repo_path, last).await?;
+            }
+        }
+
+        self.record_last_commit(repo_path, &head).await
+    }
+
+    async fn record_last_commit(&self, repo_path: &Path, commit: &str) -> anyhow::Result<()> {
+        GitRepository::create(
             &self.context,
-            embedding_texts
+            CreateGitRepositoryProps::builder()
+                .path(repo_path.to_path_buf())
+                .last_commit(commit.to_string())
+                .build(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Stores parsed documents, returning the ones that couldn't be, with
+    /// why.  They're stored together, but if that fails each is tried on its
+    /// own, so one that can't be embedded doesn't hold back the rest.  If
+    /// the index has moved on to another model, none of them can be, and
+    /// that is returned as the error instead.
+    async fn store_parsed(
+        &self,
+        documents: Vec<ParsedDocument>,
+    ) -> anyhow::Result<Vec<(PathBuf, anyhow::Error)>> {
+        if documents.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let error = match self.store_documents(&documents).await {
+            Ok(()) => return Ok(vec![]),
+            Err(error) => error,
+        };
+        self.check_embedding_model().await?;
+        if let [document] = &documents[..] {
+            return Ok(vec![(document.path.to_path_buf(), error)]);
+        }
+
+        let mut failed = vec![];
+        for document in documents {
+            if let Err(error) = self.store_documents(std::slice::from_ref(&document)).await {
+                failed.push((document.path, error));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Embeds and stores the fragments, links and Org nodes of parsed
+    /// documents, along with their hashes.  Embedding comes first, and the
+    /// rest is written in one transaction, so a document is either indexed
+    /// or left as it was to be indexed again.
+    async fn store_documents(&self, documents: &[ParsedDocument]) -> anyhow::Result<()> {
+        // The same text (a "Notes" heading, a bare "* TODO") can show up in
+        // many files, or many times in one file.  Embed each distinct text
+        // once, but keep a row for every occurrence.
+        let embedding_texts = documents
+            .iter()
+            .flat_map(|x| &x.parsed.fragments)
+            .map(|x| x.contents.to_string())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let embeddings_map = self.embed_with_cache(&embedding_texts[..]).await?;
+
+        let mut transaction = self.context.db.begin().await?;
+        EmbeddingIndex::check_current(&mut transaction, self.embeddings.model_id()).await?;
+
+        // A changed hash clears what was indexed for the file before.
+        File::create_many(
+            &mut transaction,
+            documents
                 .iter()
-                .filter_map(|(text, path)| {
-                    embeddings_map.get(text.deref()).map(|embedding| {
+                .map(|x| {
+                    CreateFileProps::builder()
+                        .path(x.path.to_path_buf())
+                        .hash(x.hash.to_owned())
+                        .build()
+                })
+                .collect(),
+        )
+        .await?;
+
+        FileEmbedding::create_many(
+            &mut transaction,
+            documents
+                .iter()
+                .flat_map(|document| {
+                    document
+                        .parsed
+                        .fragments
+                        .iter()
+                        .map(move |fragment| (document, fragment))
+                })
+                .filter_map(|(document, fragment)| {
+                    embeddings_map.get(&fragment.contents).map(|embedding| {
                         CreateFileEmbeddingProps::builder()
                             .embedding(embedding.to_owned())
-                            .file_path(path.to_path_buf())
-                            .contents(text.to_string())
+                            .file_path(document.path.to_path_buf())
+                            .contents(fragment.contents.to_string())
+                            .range(fragment.range.clone())
+                            .page(fragment.page)
+                            .heading_path(fragment.heading_path.clone())
+                            .chapter(fragment.chapter.clone())
+                            .language(fragment.language.clone())
+                            .cell(fragment.cell)
+                            .metadata(document.parsed.metadata.merged(&fragment.metadata))
+                            .kind(fragment.kind)
+                            .build()
+                    })
+                })
+                .collect(),
+        )
+        .await?;
+
+        FileLink::create_many(
+            &mut transaction,
+            documents
+                .iter()
+                .flat_map(|document| {
+                    document.parsed.links.iter().map(move |link| {
+                        CreateFileLinkProps::builder()
+                            .source_path(document.path.to_path_buf())
+                            .source_line(link.fragment_line)
+                            .target(link.target.clone())
+                            .description(link.description.clone())
+                            .build()
+                    })
+                })
+                .collect(),
+        )
+        .await?;
+
+        OrgNode::create_many(
+            &mut transaction,
+            documents
+                .iter()
+                .flat_map(|document| {
+                    document.parsed.nodes.iter().map(move |node| {
+                        CreateOrgNodeProps::builder()
+                            .id(node.id.to_string())
+                            .file_path(document.path.to_path_buf())
+                            .line(node.line)
+                            .title(node.title.clone())
                             .build()
                     })
                 })
@@ -100,6 +523,7 @@ impl IndexerService {
         )
         .await?;
 
+        transaction.commit().await?;
         Ok(())
     }
 }
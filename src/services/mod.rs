@@ -1,4 +1,9 @@
 pub mod ai;
 pub mod embeddings;
 pub mod files;
+pub mod git;
+pub mod http_embedder;
 pub mod indexer;
+pub mod parsers;
+pub mod ranking;
+pub mod reranker;
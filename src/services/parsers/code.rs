@@ -0,0 +1,413 @@
+use std::path::Path;
+
+use tree_sitter::{Language, Node, Parser};
+
+use super::{FileFragment, FragmentKind, FragmentRange, Metadata, ParsedFile};
+
+/// Chunks longer than this are split into windows of at most this many lines,
+/// and containers (impls, classes) longer than this are split into their
+/// members.
+const MAX_CHUNK_LINES: usize = 60;
+
+/// Nodes that belong to the definition directly below them: doc comments and
+/// Rust attributes.
+const LEADING_KINDS: &[&str] = &["comment", "line_comment", "block_comment", "attribute_item"];
+
+/// How to chunk a language's syntax tree.
+struct LanguageSpec {
+    name: &'static str,
+    language: fn() -> Language,
+
+    /// Nodes that define a symbol, each of which becomes its own chunk.
+    definitions: &'static [&'static str],
+
+    /// Definitions whose bodies hold further definitions, split into those
+    /// when too long to be a single chunk.
+    containers: &'static [&'static str],
+
+    /// Nodes that wrap a definition (decorators, exports), chunked as the
+    /// definition they wrap.
+    wrappers: &'static [&'static str],
+}
+
+const RUST: LanguageSpec = LanguageSpec {
+    name: "rust",
+    language: || tree_sitter_rust::LANGUAGE.into(),
+    definitions: &[
+        "function_item",
+        "function_signature_item",
+        "impl_item",
+        "trait_item",
+        "mod_item",
+        "struct_item",
+        "enum_item",
+        "union_item",
+        "macro_definition",
+    ],
+    containers: &["impl_item", "trait_item", "mod_item"],
+    wrappers: &[],
+};
+
+const PYTHON: LanguageSpec = LanguageSpec {
+    name: "python",
+    language: || tree_sitter_python::LANGUAGE.into(),
+    definitions: &["function_definition", "class_definition"],
+    containers: &["class_definition"],
+    wrappers: &["decorated_definition"],
+};
+
+const TYPESCRIPT: LanguageSpec = LanguageSpec {
+    name: "typescript",
+    language: || tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+    definitions: &[
+        "function_declaration",
+        "generator_function_declaration",
+        "class_declaration",
+        "abstract_class_declaration",
+        "method_definition",
+        "interface_declaration",
+        "type_alias_declaration",
+        "enum_declaration",
+        "lexical_declaration",
+    ],
+    containers: &["class_declaration", "abstract_class_declaration"],
+    wrappers: &["export_statement"],
+};
+
+const TSX: LanguageSpec = LanguageSpec {
+    name: "tsx",
+    language: || tree_sitter_typescript::LANGUAGE_TSX.into(),
+    ..TYPESCRIPT
+};
+
+const GO: LanguageSpec = LanguageSpec {
+    name: "go",
+    language: || tree_sitter_go::LANGUAGE.into(),
+    definitions: &[
+        "function_declaration",
+        "method_declaration",
+        "type_declaration",
+    ],
+    containers: &[],
+    wrappers: &[],
+};
+
+fn spec_for_extension(extension: &str) -> Option<&'static LanguageSpec> {
+    match extension {
+        "rs" => Some(&RUST),
+        "py" => Some(&PYTHON),
+        "ts" | "mts" | "cts" => Some(&TYPESCRIPT),
+        "tsx" => Some(&TSX),
+        "go" => Some(&GO),
+        _ => None,
+    }
+}
+
+pub fn is_supported(extension: &str) -> bool {
+    spec_for_extension(extension).is_some()
+}
+
+struct Chunker<'a> {
+    spec: &'static LanguageSpec,
+    source: &'a str,
+    fragments: Vec<FileFragment>,
+}
+
+impl<'a> Chunker<'a> {
+    /// Returns the definition a node is, or wraps.
+    fn definition<'t>(&self, node: Node<'t>) -> Option<Node<'t>> {
+        if self.spec.definitions.contains(&node.kind()) {
+            return Some(node);
+        }
+
+        if self.spec.wrappers.contains(&node.kind()) {
+            let mut cursor = node.walk();
+            let definition = node
+                .named_children(&mut cursor)
+                .find(|x| self.spec.definitions.contains(&x.kind()));
+            return definition;
+        }
+
+        None
+    }
+
+    /// The definition's signature: everything before its body, on one line.
+    fn signature(&self, definition: Node) -> String {
+        let end = definition
+            .child_by_field_name("body")
+            .map(|x| x.start_byte())
+            .unwrap_or_else(|| {
+                let text = &self.source[definition.start_byte()..definition.end_byte()];
+                definition.start_byte() + text.find('\n').unwrap_or(text.len())
+            });
+
+        self.source[definition.start_byte()..end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_end_matches(['{', ':', '='])
+            .trim()
+            .to_string()
+    }
+
+    /// Emits the source between two byte offsets as fragments, split into
+    /// windows of at most `MAX_CHUNK_LINES` lines.
+    fn emit(&mut self, kind: FragmentKind, start: usize, end: usize, heading_path: &[String]) {
+        let text = &self.source[start..end];
+        if text.trim().is_empty() {
+            return;
+        }
+
+        let first_line = self.source[..start].matches('\n').count() + 1;
+        let mut offset = start;
+        let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+
+        for (index, window) in lines.chunks(MAX_CHUNK_LINES).enumerate() {
+            let contents = window.concat();
+            let window_start = offset;
+            offset += contents.len();
+
+            if contents.trim().is_empty() {
+                continue;
+            }
+
+            self.fragments.push(FileFragment {
+                kind,
+                contents: contents.trim_end().to_string(),
+                range: FragmentRange {
+                    start_line: first_line + index * MAX_CHUNK_LINES,
+                    end_line: first_line + index * MAX_CHUNK_LINES + window.len() - 1,
+                    start_byte: window_start,
+                    end_byte: window_start + contents.trim_end().len(),
+                },
+                heading_path: heading_path.to_vec(),
+                page: None,
+                chapter: None,
+                language: Some(self.spec.name.to_string()),
+                cell: None,
+                metadata: Metadata::default(),
+            });
+        }
+    }
+
+    /// Chunks the named children of a node.  Definitions become chunks of
+    /// their own along with the comments and attributes directly above them,
+    /// and whatever sits between definitions (imports, statements) is kept
+    /// together.
+    fn chunk_children(&mut self, node: Node, heading_path: &[String]) {
+        let mut cursor = node.walk();
+        let children = node.named_children(&mut cursor).collect::<Vec<_>>();
+
+        let mut gap: Option<(usize, usize)> = None;
+        let mut comments: Option<(usize, usize)> = None;
+        let mut previous_row = None;
+
+        for child in children {
+            let adjacent = previous_row.is_some_and(|x: usize| child.start_position().row <= x + 1);
+            previous_row = Some(child.end_position().row);
+
+            if LEADING_KINDS.contains(&child.kind()) {
+                comments = match comments {
+                    Some((start, _)) if adjacent => Some((start, child.end_byte())),
+                    Some(previous) => {
+                        gap = Some(extend(gap, previous));
+                        Some((child.start_byte(), child.end_byte()))
+                    }
+                    None => Some((child.start_byte(), child.end_byte())),
+                };
+                continue;
+            }
+
+            if let Some(definition) = self.definition(child) {
+                // Comments only document the definition if they sit directly
+                // above it.
+                let start = match comments.take() {
+                    Some((start, _)) if adjacent => start,
+                    Some(previous) => {
+                        gap = Some(extend(gap, previous));
+                        child.start_byte()
+                    }
+                    None => child.start_byte(),
+                };
+
+                if let Some((gap_start, gap_end)) = gap.take() {
+                    self.emit(FragmentKind::Paragraph, gap_start, gap_end, heading_path);
+                }
+
+                self.chunk_definition(start, child, definition, heading_path);
+            } else {
+                if let Some(previous) = comments.take() {
+                    gap = Some(extend(gap, previous));
+                }
+                gap = Some(extend(gap, (child.start_byte(), child.end_byte())));
+            }
+        }
+
+        if let Some(previous) = comments.take() {
+            gap = Some(extend(gap, previous));
+        }
+        if let Some((gap_start, gap_end)) = gap.take() {
+            self.emit(FragmentKind::Paragraph, gap_start, gap_end, heading_path);
+        }
+    }
+
+    fn chunk_definition(&mut self, start: usize, node: Node, definition: Node, parents: &[String]) {
+        let mut heading_path = parents.to_vec();
+        heading_path.push(self.signature(definition));
+
+        let lines = node.end_position().row - node.start_position().row + 1;
+        let body = definition.child_by_field_name("body");
+
+        match body {
+            Some(body)
+                if lines > MAX_CHUNK_LINES && self.spec.containers.contains(&definition.kind()) =>
+            {
+                // Too long to embed whole, so the container's header (its doc
+                // comments and signature) stands in as a heading for its
+                // members.
+                self.emit(
+                    FragmentKind::Heading,
+                    start,
+                    body.start_byte(),
+                    &heading_path,
+                );
+                self.chunk_children(body, &heading_path);
+            }
+            _ => self.emit(
+                FragmentKind::Paragraph,
+                start,
+                node.end_byte(),
+                &heading_path,
+            ),
+        }
+    }
+}
+
+fn extend(range: Option<(usize, usize)>, (start, end): (usize, usize)) -> (usize, usize) {
+    match range {
+        Some((existing_start, _)) => (existing_start, end),
+        None => (start, end),
+    }
+}
+
+fn chunk(spec: &'static LanguageSpec, source: &str) -> anyhow::Result<Vec<FileFragment>> {
+    let mut parser = Parser::new();
+    parser.set_language(&(spec.language)())?;
+    let tree = parser
+        .parse(source, None)
+        .ok_or_else(|| anyhow::anyhow!("Could not parse {} source", spec.name))?;
+
+    let mut chunker = Chunker {
+        spec,
+        source,
+        fragments: vec![],
+    };
+    chunker.chunk_children(tree.root_node(), &[]);
+
+    Ok(chunker.fragments)
+}
+
+/// Parses a source file into one fragment per top-level definition, headed by
+/// the signatures of the definitions enclosing it.  Long impls and classes
+/// are split into their members.  Every fragment is tagged with the file's
+/// language.
+pub async fn parse(path: &Path, extension: &str) -> anyhow::Result<ParsedFile> {
+    let spec = spec_for_extension(extension)
+        .ok_or_else(|| anyhow::anyhow!("No code parser for .{extension} files"))?;
+    let source = tokio::fs::read_to_string(path).await?;
+
+    let mut parsed = ParsedFile::from(chunk(spec, &source)?);
+    parsed.metadata.insert("language", spec.name);
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(fragments: &[FileFragment]) -> Vec<&str> {
+        fragments.iter().map(|x| x.contents.as_str()).collect()
+    }
+
+    #[test]
+    fn definitions_keep_the_comments_directly_above_them() {
+        let source = "use std::fmt;\n\
+                      \n\
+                      /// Adds one.\n\
+                      #[inline]\n\
+                      fn add_one(x: u32) -> u32 {\n    x + 1\n}\n\
+                      \n\
+                      // Stray note.\n\
+                      \n\
+                      struct Point {\n    x: u32,\n}\n";
+        let fragments = chunk(&RUST, source).unwrap();
+
+        assert_eq!(
+            contents(&fragments),
+            [
+                "use std::fmt;",
+                "/// Adds one.\n#[inline]\nfn add_one(x: u32) -> u32 {\n    x + 1\n}",
+                "// Stray note.",
+                "struct Point {\n    x: u32,\n}",
+            ]
+        );
+        assert_eq!(fragments[1].heading_path, ["fn add_one(x: u32) -> u32"]);
+        assert_eq!(fragments[1].range.start_line, 3);
+        assert_eq!(fragments[1].range.end_line, 7);
+        assert_eq!(
+            &source[fragments[1].range.start_byte..fragments[1].range.end_byte],
+            fragments[1].contents
+        );
+        assert_eq!(fragments[3].heading_path, ["struct Point"]);
+        assert_eq!(fragments[3].language.as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn long_containers_are_split_into_their_members() {
+        let filler = "        let _ = 0;\n".repeat(MAX_CHUNK_LINES);
+        let source = format!(
+            "class Big:\n    \"\"\"Docs.\"\"\"\n\n    @property\n    def first(self):\n{filler}\n    def second(self):\n        pass\n"
+        );
+        let fragments = chunk(&PYTHON, &source).unwrap();
+
+        assert_eq!(fragments[0].kind, FragmentKind::Heading);
+        assert_eq!(fragments[0].contents, "class Big:");
+        assert_eq!(fragments[1].contents, "\"\"\"Docs.\"\"\"");
+
+        let first = &fragments[2];
+        assert!(first
+            .contents
+            .starts_with("@property\n    def first(self):"));
+        assert_eq!(first.heading_path, ["class Big", "def first(self)"]);
+        assert_eq!(
+            first.range.end_line - first.range.start_line + 1,
+            MAX_CHUNK_LINES
+        );
+
+        // The rest of `first` spills into a second window.
+        assert_eq!(fragments[3].heading_path, ["class Big", "def first(self)"]);
+        assert_eq!(fragments[3].range.start_line, first.range.end_line + 1);
+
+        let second = fragments.last().unwrap();
+        assert_eq!(second.contents, "def second(self):\n        pass");
+        assert_eq!(second.heading_path, ["class Big", "def second(self)"]);
+    }
+
+    #[test]
+    fn wrappers_are_chunked_as_the_definition_they_wrap() {
+        let source = "import { a } from \"a\";\n\
+                      export function greet(name: string): string {\n  return name;\n}\n\
+                      export const LIMIT = 3;\n";
+        let fragments = chunk(&TYPESCRIPT, source).unwrap();
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0].contents, "import { a } from \"a\";");
+        assert_eq!(
+            fragments[1].heading_path,
+            ["function greet(name: string): string"]
+        );
+        assert!(fragments[1].contents.starts_with("export function greet"));
+        assert_eq!(fragments[2].heading_path, ["const LIMIT = 3;"]);
+    }
+}
@@ -0,0 +1,583 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use zip::ZipArchive;
+
+use super::{html, FileFragment, Metadata, ParsedFile};
+
+type Archive = ZipArchive<Cursor<Vec<u8>>>;
+
+struct ManifestItem {
+    href: String,
+    media_type: String,
+    properties: String,
+}
+
+/// The parts of an OPF package document needed to walk a book.
+#[derive(Default)]
+struct Package {
+    metadata: Metadata,
+    manifest: HashMap<String, ManifestItem>,
+    spine: Vec<String>,
+
+    /// The manifest id of the EPUB 2 NCX table of contents, if any.
+    ncx_id: Option<String>,
+}
+
+/// An entry in the book's table of contents.
+struct TocEntry {
+    /// The titles of this entry and all of its parents, outermost first.
+    path: Vec<String>,
+
+    /// The chapter this entry points into, as a path inside the archive.
+    chapter: String,
+    anchor: Option<String>,
+}
+
+fn read_entry(archive: &mut Archive, name: &str) -> anyhow::Result<String> {
+    let mut contents = String::new();
+    archive
+        .by_name(name)
+        .with_context(|| format!("EPUB is missing {name}"))?
+        .read_to_string(&mut contents)?;
+
+    Ok(contents)
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|x| x.key.local_name().as_ref() == name)
+        .and_then(|x| x.unescape_value().ok().map(|x| x.to_string()))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Resolves an href found in the document at `base_dir` to a path inside the
+/// archive, splitting off any `#fragment`.
+fn resolve_href(base_dir: &str, href: &str) -> (String, Option<String>) {
+    let (href, anchor) = match href.split_once('#') {
+        Some((href, anchor)) => (href, Some(percent_decode(anchor))),
+        None => (href, None),
+    };
+
+    let mut parts: Vec<&str> = base_dir.split('/').filter(|x| !x.is_empty()).collect();
+    let href = percent_decode(href);
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    (parts.join("/"), anchor)
+}
+
+fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn find_rootfile(container: &str) -> anyhow::Result<String> {
+    let mut reader = Reader::from_str(container);
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+                if let Some(path) = attribute(&e, b"full-path") {
+                    return Ok(path);
+                }
+            }
+            Event::Eof => return Err(anyhow!("EPUB container lists no package document")),
+            _ => {}
+        }
+    }
+}
+
+fn parse_package(opf: &str) -> anyhow::Result<Package> {
+    let mut reader = Reader::from_str(opf);
+    reader.trim_text(true);
+
+    let mut package = Package::default();
+    let mut in_metadata = false;
+    let mut current_field: Option<&'static str> = None;
+
+    loop {
+        let event = reader.read_event()?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_start = matches!(event, Event::Start(_));
+                match e.local_name().as_ref() {
+                    b"item" => {
+                        if let (Some(id), Some(href)) = (attribute(e, b"id"), attribute(e, b"href"))
+                        {
+                            package.manifest.insert(
+                                id,
+                                ManifestItem {
+                                    href,
+                                    media_type: attribute(e, b"media-type").unwrap_or_default(),
+                                    properties: attribute(e, b"properties").unwrap_or_default(),
+                                },
+                            );
+                        }
+                    }
+                    b"itemref" if attribute(e, b"linear").as_deref() != Some("no") => {
+                        package.spine.extend(attribute(e, b"idref"));
+                    }
+                    b"spine" => package.ncx_id = attribute(e, b"toc"),
+                    b"metadata" => in_metadata = is_start,
+                    b"title" if in_metadata && is_start => current_field = Some("title"),
+                    b"creator" if in_metadata && is_start => current_field = Some("author"),
+                    b"language" if in_metadata && is_start => current_field = Some("language"),
+                    _ => {}
+                }
+            }
+            Event::Text(text) => {
+                if let Some(field) = current_field {
+                    // Only the first title or creator is the book's own.
+                    if !package.metadata.contains_key(field) {
+                        package.metadata.insert(field, text.unescape()?);
+                    }
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"metadata" => in_metadata = false,
+                _ => current_field = None,
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(package)
+}
+
+/// Reads an EPUB 2 NCX table of contents, whose `navPoint`s nest to form the
+/// heading structure.
+fn parse_ncx(ncx: &str, base_dir: &str) -> anyhow::Result<Vec<TocEntry>> {
+    let mut reader = Reader::from_str(ncx);
+    reader.trim_text(true);
+
+    let mut entries = vec![];
+    let mut titles: Vec<String> = vec![];
+    let mut in_label = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"navPoint" => titles.push(String::new()),
+                b"navLabel" => in_label = true,
+                _ => {}
+            },
+            Event::Empty(e) if e.local_name().as_ref() == b"content" => {
+                if let Some(src) = attribute(&e, b"src") {
+                    let (chapter, anchor) = resolve_href(base_dir, &src);
+                    entries.push(TocEntry {
+                        path: titles.clone(),
+                        chapter,
+                        anchor,
+                    });
+                }
+            }
+            Event::Text(text) if in_label => {
+                if let Some(title) = titles.last_mut() {
+                    title.push_str(&text.unescape()?);
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"navPoint" => {
+                    titles.pop();
+                }
+                b"navLabel" => in_label = false,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads an EPUB 3 navigation document, whose `<nav epub:type="toc">` holds
+/// nested lists of links.
+fn parse_nav(nav: &str, base_dir: &str) -> anyhow::Result<Vec<TocEntry>> {
+    let mut reader = Reader::from_str(nav);
+
+    let mut entries = vec![];
+    let mut titles: Vec<String> = vec![];
+    let mut toc_depth = 0;
+    let mut link: Option<(String, String)> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"nav" if toc_depth > 0 => toc_depth += 1,
+                b"nav"
+                    if attribute(&e, b"type")
+                        .is_some_and(|x| x.split_whitespace().any(|x| x == "toc")) =>
+                {
+                    toc_depth = 1
+                }
+                b"li" if toc_depth > 0 => titles.push(String::new()),
+                b"a" if toc_depth > 0 => {
+                    link = attribute(&e, b"href").map(|href| (href, String::new()));
+                }
+                _ => {}
+            },
+            Event::Text(text) => {
+                if let Some((_, title)) = link.as_mut() {
+                    // XHTML entities like &nbsp; aren't known to an XML reader,
+                    // so fall back to the raw text rather than fail the book.
+                    match text.unescape() {
+                        Ok(text) => title.push_str(&text),
+                        Err(_) => title.push_str(&String::from_utf8_lossy(&text)),
+                    }
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"nav" if toc_depth > 0 => toc_depth -= 1,
+                b"li" if toc_depth > 0 => {
+                    titles.pop();
+                }
+                b"a" => {
+                    if let Some((href, title)) = link.take() {
+                        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+                        if let Some(last) = titles.last_mut() {
+                            *last = title;
+                        }
+
+                        let (chapter, anchor) = resolve_href(base_dir, &href);
+                        entries.push(TocEntry {
+                            path: titles.clone(),
+                            chapter,
+                            anchor,
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+fn read_toc(archive: &mut Archive, package: &Package, opf_dir: &str) -> Vec<TocEntry> {
+    let nav = package
+        .manifest
+        .values()
+        .find(|x| x.properties.split_whitespace().any(|x| x == "nav"));
+    let ncx = package
+        .ncx_id
+        .as_ref()
+        .and_then(|x| package.manifest.get(x));
+
+    // A missing or malformed table of contents only costs us heading paths,
+    // so it shouldn't keep the book out of the index.
+    if let Some(item) = nav {
+        let (path, _) = resolve_href(opf_dir, &item.href);
+        if let Ok(entries) =
+            read_entry(archive, &path).and_then(|x| parse_nav(&x, parent_dir(&path)))
+        {
+            return entries;
+        }
+    }
+
+    if let Some(item) = ncx {
+        let (path, _) = resolve_href(opf_dir, &item.href);
+        if let Ok(entries) =
+            read_entry(archive, &path).and_then(|x| parse_ncx(&x, parent_dir(&path)))
+        {
+            return entries;
+        }
+    }
+
+    vec![]
+}
+
+/// Prefixes the heading paths of a chapter's fragments with where the chapter
+/// sits in the table of contents.  Fragments after an anchor the table of
+/// contents links to get that entry's path, the rest get the chapter's.
+fn apply_toc(fragments: &mut [FileFragment], anchors: &[(String, usize)], toc: &[&TocEntry]) {
+    let chapter_path = toc
+        .iter()
+        .find(|x| x.anchor.is_none())
+        .or(toc.first())
+        .map(|x| x.path.clone())
+        .unwrap_or_default();
+
+    let mut sections = toc
+        .iter()
+        .filter_map(|entry| {
+            let anchor = entry.anchor.as_ref()?;
+            anchors
+                .iter()
+                .find(|(id, _)| id == anchor)
+                .map(|(_, line)| (*line, &entry.path))
+        })
+        .collect::<Vec<_>>();
+    sections.sort_by_key(|(line, _)| *line);
+
+    for fragment in fragments {
+        let toc_path = sections
+            .iter()
+            .rev()
+            .find(|(line, _)| *line <= fragment.range.start_line)
+            .map(|(_, path)| (*path).clone())
+            .unwrap_or_else(|| chapter_path.clone());
+
+        // Chapters usually repeat their table of contents titles as headings,
+        // so only the headings nested below the last repeated one are added.
+        let seen = toc_path
+            .iter()
+            .map(|x| normalize_title(x))
+            .collect::<Vec<_>>();
+        let nested_from = fragment
+            .heading_path
+            .iter()
+            .rposition(|x| seen.contains(&normalize_title(x)))
+            .map(|x| x + 1)
+            .unwrap_or(0);
+        let headings = fragment.heading_path.split_off(nested_from);
+
+        fragment.heading_path = toc_path.into_iter().chain(headings).collect();
+    }
+}
+
+fn parse_archive(bytes: Vec<u8>) -> anyhow::Result<ParsedFile> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    let opf_path = find_rootfile(&read_entry(&mut archive, "META-INF/container.xml")?)?;
+    let opf_dir = parent_dir(&opf_path).to_string();
+    let package = parse_package(&read_entry(&mut archive, &opf_path)?)?;
+    let toc = read_toc(&mut archive, &package, &opf_dir);
+
+    let mut fragments = vec![];
+
+    for idref in &package.spine {
+        let Some(item) = package.manifest.get(idref) else {
+            continue;
+        };
+
+        if !item.media_type.contains("html") {
+            continue;
+        }
+
+        let (chapter, _) = resolve_href(&opf_dir, &item.href);
+        let Ok(contents) = read_entry(&mut archive, &chapter) else {
+            continue;
+        };
+
+        let mut document = html::extract(&contents, false);
+        let chapter_toc = toc
+            .iter()
+            .filter(|x| x.chapter == chapter)
+            .collect::<Vec<_>>();
+        apply_toc(&mut document.fragments, &document.anchors, &chapter_toc);
+
+        fragments.extend(document.fragments.into_iter().map(|x| FileFragment {
+            chapter: Some(chapter.to_string()),
+            ..x
+        }));
+    }
+
+    Ok(ParsedFile {
+        metadata: package.metadata,
+        fragments,
+        links: vec![],
+        nodes: vec![],
+    })
+}
+
+/// Parses an EPUB book chapter by chapter, following the order of its spine.
+/// Each chapter's fragments are located by the chapter's path in the book and
+/// line within the chapter, and headed by the table of contents entries that
+/// lead to them.
+pub async fn parse(path: &Path) -> anyhow::Result<ParsedFile> {
+    let bytes = tokio::fs::read(path).await?;
+
+    tokio::task::spawn_blocking(move || parse_archive(bytes))
+        .await
+        .context("EPUB parsing panicked")?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    const CONTAINER: &str = r#"<container><rootfiles>
+        <rootfile full-path="OEBPS/content.opf"/>
+    </rootfiles></container>"#;
+
+    const PACKAGE: &str = r#"<package>
+        <metadata>
+            <dc:title>A Book</dc:title>
+            <dc:creator>Jane Doe</dc:creator>
+            <dc:creator>John Doe</dc:creator>
+        </metadata>
+        <manifest>
+            <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+            <item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+            <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+            <item id="two" href="text/two%20b.xhtml" media-type="application/xhtml+xml"/>
+        </manifest>
+        <spine>
+            <itemref idref="cover" linear="no"/>
+            <itemref idref="two"/>
+            <itemref idref="one"/>
+        </spine>
+    </package>"#;
+
+    const NAV: &str = r#"<html><body><nav epub:type="toc"><ol>
+        <li><a href="text/one.xhtml">Part One</a>
+            <ol><li><a href="text/one.xhtml#later">Later On</a></li></ol>
+        </li>
+        <li><a href="text/two%20b.xhtml">Part Two</a></li>
+    </ol></nav></body></html>"#;
+
+    const ONE: &str = "<html><body>\n\
+                       <h1>Part One</h1>\n\
+                       <p>It begins.</p>\n\
+                       <h2 id=\"later\">Later On</h2>\n\
+                       <p>It goes on.</p>\n\
+                       </body></html>";
+
+    const TWO: &str = "<html><body><p>Second.</p></body></html>";
+
+    fn book() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in [
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", PACKAGE),
+            ("OEBPS/nav.xhtml", NAV),
+            ("OEBPS/cover.xhtml", "<p>Cover</p>"),
+            ("OEBPS/text/one.xhtml", ONE),
+            ("OEBPS/text/two b.xhtml", TWO),
+        ] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn resolve_href_splits_anchors_and_walks_up() {
+        assert_eq!(
+            resolve_href("OEBPS/text", "../images/a%20b.png#top"),
+            ("OEBPS/images/a b.png".to_string(), Some("top".to_string()))
+        );
+        assert_eq!(
+            resolve_href("", "./chapter.xhtml"),
+            ("chapter.xhtml".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn chapters_follow_the_spine_headed_by_the_table_of_contents() {
+        let parsed = parse_archive(book()).unwrap();
+
+        assert_eq!(
+            parsed.metadata.get("title"),
+            Some(&["A Book".to_string()][..])
+        );
+        assert_eq!(
+            parsed.metadata.get("author"),
+            Some(&["Jane Doe".to_string()][..])
+        );
+
+        let fragments = parsed
+            .fragments
+            .iter()
+            .map(|x| {
+                (
+                    x.chapter.as_deref().unwrap(),
+                    x.contents.as_str(),
+                    x.heading_path.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            fragments,
+            [
+                (
+                    "OEBPS/text/two b.xhtml",
+                    "Second.",
+                    vec!["Part Two".to_string()]
+                ),
+                (
+                    "OEBPS/text/one.xhtml",
+                    "Part One",
+                    vec!["Part One".to_string()]
+                ),
+                (
+                    "OEBPS/text/one.xhtml",
+                    "It begins.",
+                    vec!["Part One".to_string()]
+                ),
+                (
+                    "OEBPS/text/one.xhtml",
+                    "Later On",
+                    vec!["Part One".to_string(), "Later On".to_string()]
+                ),
+                (
+                    "OEBPS/text/one.xhtml",
+                    "It goes on.",
+                    vec!["Part One".to_string(), "Later On".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(parsed.fragments[4].range.start_line, 5);
+    }
+}
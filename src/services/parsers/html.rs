@@ -0,0 +1,570 @@
+use std::path::Path;
+
+use html5ever::{
+    tendril::StrTendril,
+    tokenizer::{
+        states::RawKind, BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer,
+        TokenizerOpts,
+    },
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{FileFragment, FragmentKind, FragmentRange, HeadingStack, Metadata, ParsedFile};
+
+/// Pages that mark up their content with `<main>` or `<article>` get
+/// everything outside of those dropped as boilerplate.
+static CONTENT_ROOT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)<(main|article)[\s>]").unwrap());
+
+/// Class and id names that mark an element as page chrome rather than
+/// content.
+static BOILERPLATE_ATTR_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(nav|navbar|menu|sidebar|footer|cookies?|banner|ads?|advert|share|social|related|comments?|breadcrumbs?|newsletter|subscribe|popup|modal)\b",
+    )
+    .unwrap()
+});
+
+/// Elements that never hold readable content, whose whole subtree is skipped.
+const SKIPPED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "iframe", "button", "select", "object",
+    "canvas",
+];
+
+/// Elements that hold page chrome rather than content, skipped when stripping
+/// boilerplate.
+const CHROME_TAGS: &[&str] = &["nav", "aside", "footer", "form", "menu", "dialog"];
+
+/// Landmark roles that mark navigation and other chrome.
+const SKIPPED_ROLES: &[&str] = &[
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+];
+
+/// Elements that break text onto a new line.
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "hr",
+    "li",
+    "ul",
+    "ol",
+    "dl",
+    "dt",
+    "dd",
+    "pre",
+    "blockquote",
+    "table",
+    "tr",
+    "td",
+    "th",
+    "section",
+    "article",
+    "main",
+    "header",
+    "figure",
+    "figcaption",
+];
+
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose end tag may be left out, closed instead by the next
+/// sibling or by their parent.  The tokenizer can't tell where they end, so
+/// they're never skipped by their attributes.
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "li", "p", "dt", "dd", "tr", "td", "th", "thead", "tbody", "tfoot", "option", "optgroup",
+    "colgroup", "caption", "rb", "rt", "rtc", "rp", "html", "head", "body",
+];
+
+fn heading_level(name: &str) -> Option<u8> {
+    match name {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn attr<'a>(tag: &'a Tag, name: &str) -> Option<&'a str> {
+    tag.attrs
+        .iter()
+        .find(|x| &*x.name.local == name)
+        .map(|x| &*x.value)
+}
+
+/// A fragment collected while tokenizing, located by the lines of the source
+/// it was read from.
+struct PendingFragment {
+    kind: FragmentKind,
+    contents: String,
+    heading_path: Vec<String>,
+    start_line: u64,
+    end_line: u64,
+}
+
+/// Collects readable text out of a token stream.  The tokenizer is used
+/// directly rather than building a DOM because it reports the source line of
+/// every token, which lets fragments point back into the original file.
+#[derive(Default)]
+struct HtmlSink {
+    strip_chrome: bool,
+    has_content_root: bool,
+    content_depth: usize,
+
+    /// The element currently being skipped, and how deeply it's nested in
+    /// itself.
+    skipping: Option<(String, usize)>,
+    in_title: bool,
+    pre_depth: usize,
+    heading: Option<u8>,
+    headings: HeadingStack,
+
+    buffer: String,
+    start_line: Option<u64>,
+    end_line: u64,
+
+    fragments: Vec<PendingFragment>,
+    anchors: Vec<(String, u64)>,
+    title: Option<String>,
+    canonical_url: Option<String>,
+    og_title: Option<String>,
+    og_url: Option<String>,
+}
+
+impl HtmlSink {
+    fn new(strip_chrome: bool, has_content_root: bool) -> Self {
+        HtmlSink {
+            strip_chrome,
+            has_content_root,
+            ..Default::default()
+        }
+    }
+
+    fn collecting(&self) -> bool {
+        self.skipping.is_none() && (!self.has_content_root || self.content_depth > 0)
+    }
+
+    fn should_skip(&self, tag: &Tag) -> bool {
+        let name = &*tag.name;
+
+        if SKIPPED_TAGS.contains(&name) {
+            return true;
+        }
+
+        if !self.strip_chrome {
+            return false;
+        }
+
+        // Without a content root, a page header is as much chrome as its
+        // navigation.  Inside one, it usually holds the article's title.
+        if CHROME_TAGS.contains(&name) || (name == "header" && !self.has_content_root) {
+            return true;
+        }
+
+        if OPTIONAL_END_TAGS.contains(&name) {
+            return false;
+        }
+
+        if attr(tag, "hidden").is_some() || attr(tag, "aria-hidden") == Some("true") {
+            return true;
+        }
+
+        if attr(tag, "role").is_some_and(|x| SKIPPED_ROLES.contains(&x)) {
+            return true;
+        }
+
+        ["class", "id"]
+            .iter()
+            .filter_map(|x| attr(tag, x))
+            .any(|x| BOILERPLATE_ATTR_REGEX.is_match(x))
+    }
+
+    fn push_newline(&mut self) {
+        if !self.buffer.is_empty() && !self.buffer.ends_with('\n') {
+            self.buffer.push('\n');
+        }
+    }
+
+    fn push_text(&mut self, text: &str, line: u64) {
+        if self.pre_depth > 0 {
+            self.buffer.push_str(text);
+        } else {
+            for c in text.chars() {
+                if c.is_whitespace() {
+                    if !self.buffer.is_empty()
+                        && !self.buffer.ends_with(' ')
+                        && !self.buffer.ends_with('\n')
+                    {
+                        self.buffer.push(' ');
+                    }
+                } else {
+                    self.buffer.push(c);
+                }
+            }
+        }
+
+        if !text.trim().is_empty() {
+            self.start_line.get_or_insert(line);
+            self.end_line = line;
+        }
+    }
+
+    /// Turns whatever text has been collected into a fragment of the given
+    /// kind.
+    fn flush(&mut self, kind: FragmentKind) {
+        let contents = self
+            .buffer
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.buffer.clear();
+
+        if let Some(start_line) = self.start_line.take() {
+            if !contents.is_empty() {
+                if let (FragmentKind::Heading, Some(level)) = (kind, self.heading) {
+                    self.headings.push(level as usize, contents.to_string());
+                }
+
+                self.fragments.push(PendingFragment {
+                    kind,
+                    contents,
+                    heading_path: self.headings.path(),
+                    start_line,
+                    end_line: self.end_line,
+                });
+            }
+        }
+    }
+
+    fn process_tag(&mut self, tag: Tag, line_number: u64) -> TokenSinkResult<()> {
+        let name = tag.name.to_string();
+
+        if let Some((skipped, depth)) = self.skipping.as_mut() {
+            if *skipped == name {
+                match tag.kind {
+                    TagKind::StartTag if !tag.self_closing => *depth += 1,
+                    TagKind::EndTag => *depth -= 1,
+                    _ => {}
+                }
+
+                if *depth == 0 {
+                    self.skipping = None;
+                }
+            }
+
+            return TokenSinkResult::Continue;
+        }
+
+        match tag.kind {
+            TagKind::StartTag => {
+                match name.as_str() {
+                    "title" => {
+                        self.in_title = true;
+                        return TokenSinkResult::RawData(RawKind::Rcdata);
+                    }
+                    "link"
+                        if attr(&tag, "rel").is_some_and(|x| {
+                            x.split_whitespace()
+                                .any(|x| x.eq_ignore_ascii_case("canonical"))
+                        }) =>
+                    {
+                        self.canonical_url = attr(&tag, "href").map(|x| x.to_string());
+                    }
+                    "meta" => match attr(&tag, "property") {
+                        Some("og:title") => {
+                            self.og_title = attr(&tag, "content").map(|x| x.to_string())
+                        }
+                        Some("og:url") => {
+                            self.og_url = attr(&tag, "content").map(|x| x.to_string())
+                        }
+                        _ => {}
+                    },
+                    "main" | "article" => self.content_depth += 1,
+                    _ => {}
+                }
+
+                if let Some(id) = attr(&tag, "id") {
+                    self.anchors.push((id.to_string(), line_number));
+                }
+
+                if !self.collecting() || VOID_TAGS.contains(&name.as_str()) {
+                    if name == "br" && self.collecting() {
+                        self.push_newline();
+                    }
+
+                    return TokenSinkResult::Continue;
+                }
+
+                if self.should_skip(&tag) {
+                    if !tag.self_closing {
+                        self.skipping = Some((name.to_string(), 1));
+                    }
+
+                    return match name.as_str() {
+                        "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+                        "style" | "noscript" | "iframe" => {
+                            TokenSinkResult::RawData(RawKind::Rawtext)
+                        }
+                        _ => TokenSinkResult::Continue,
+                    };
+                }
+
+                if let Some(level) = heading_level(&name) {
+                    self.flush(FragmentKind::Paragraph);
+                    self.heading = Some(level);
+                } else if name == "pre" {
+                    self.pre_depth += 1;
+                    self.push_newline();
+                } else if BLOCK_TAGS.contains(&name.as_str()) {
+                    self.push_newline();
+                }
+            }
+
+            TagKind::EndTag => {
+                match name.as_str() {
+                    "title" => self.in_title = false,
+                    "main" | "article" => self.content_depth = self.content_depth.saturating_sub(1),
+                    "pre" => self.pre_depth = self.pre_depth.saturating_sub(1),
+                    _ => {}
+                }
+
+                if heading_level(&name).is_some() && self.heading.is_some() {
+                    self.flush(FragmentKind::Heading);
+                    self.heading = None;
+                } else if BLOCK_TAGS.contains(&name.as_str()) {
+                    self.push_newline();
+                }
+            }
+        }
+
+        TokenSinkResult::Continue
+    }
+}
+
+impl TokenSink for HtmlSink {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => return self.process_tag(tag, line_number),
+            Token::CharacterTokens(text) => {
+                if self.in_title {
+                    self.title.get_or_insert_with(String::new).push_str(&text);
+                } else if self.collecting() {
+                    self.push_text(&text, line_number);
+                }
+            }
+            Token::EOFToken => {
+                let kind = if self.heading.is_some() {
+                    FragmentKind::Heading
+                } else {
+                    FragmentKind::Paragraph
+                };
+                self.flush(kind);
+            }
+            _ => {}
+        }
+
+        TokenSinkResult::Continue
+    }
+}
+
+/// Text extracted from an HTML document.  Fragment ranges refer to lines of
+/// the document the text was extracted from.
+pub struct HtmlDocument {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub fragments: Vec<FileFragment>,
+
+    /// Element ids in the document, with the line each element starts on.
+    pub anchors: Vec<(String, usize)>,
+}
+
+/// Extracts headings and paragraphs from an HTML document.  Scripts and
+/// styles are always dropped; with `strip_chrome`, so are navigation, footers
+/// and other boilerplate, and if the page has a `<main>` or `<article>`
+/// everything outside of it.
+pub fn extract(contents: &str, strip_chrome: bool) -> HtmlDocument {
+    let mut queue = BufferQueue::default();
+    queue.push_back(StrTendril::from_slice(contents));
+
+    let sink = HtmlSink::new(
+        strip_chrome,
+        strip_chrome && CONTENT_ROOT_REGEX.is_match(contents),
+    );
+    let mut tokenizer = Tokenizer::new(sink, TokenizerOpts::default());
+    let _ = tokenizer.feed(&mut queue);
+    tokenizer.end();
+    let sink = tokenizer.sink;
+
+    // Byte offsets of the start of each line, to turn the tokenizer's line
+    // numbers back into byte ranges.
+    let line_starts = std::iter::once(0)
+        .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+    let line_end = |line: usize| {
+        line_starts
+            .get(line)
+            .map(|x| x - 1)
+            .unwrap_or(contents.len())
+    };
+
+    let fragments = sink
+        .fragments
+        .into_iter()
+        .map(|x| {
+            let start_line = x.start_line as usize;
+            let end_line = x.end_line as usize;
+
+            FileFragment {
+                kind: x.kind,
+                contents: x.contents,
+                range: FragmentRange {
+                    start_line,
+                    end_line,
+                    start_byte: line_starts.get(start_line - 1).copied().unwrap_or(0),
+                    end_byte: line_end(end_line),
+                },
+                heading_path: x.heading_path,
+                page: None,
+                chapter: None,
+                language: None,
+                cell: None,
+                metadata: Metadata::default(),
+            }
+        })
+        .collect();
+
+    let title = sink
+        .title
+        .or(sink.og_title)
+        .map(|x| x.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|x| !x.is_empty());
+
+    HtmlDocument {
+        title,
+        url: sink.canonical_url.or(sink.og_url),
+        fragments,
+        anchors: sink
+            .anchors
+            .into_iter()
+            .map(|(id, line)| (id, line as usize))
+            .collect(),
+    }
+}
+
+/// Parses a saved web page.  Scripts, navigation and other page chrome are
+/// dropped, `<h1>`–`<h6>` become heading fragments, and the text between them
+/// becomes paragraphs.  The page's title and canonical URL are recorded as
+/// metadata.
+pub async fn parse(path: &Path) -> anyhow::Result<ParsedFile> {
+    let file_contents = tokio::fs::read_to_string(path).await?;
+    let document = extract(&file_contents, true);
+
+    let mut metadata = Metadata::default();
+    if let Some(title) = document.title {
+        metadata.insert("title", title);
+    }
+    if let Some(url) = document.url {
+        metadata.insert("url", url);
+    }
+
+    Ok(ParsedFile {
+        metadata,
+        fragments: document.fragments,
+        links: vec![],
+        nodes: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(document: &HtmlDocument) -> Vec<(FragmentKind, &str)> {
+        document
+            .fragments
+            .iter()
+            .map(|x| (x.kind, x.contents.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn chrome_and_text_outside_the_main_content_are_dropped() {
+        let page = "<html><head><title>Release\n  notes</title>\n\
+                    <link rel=\"canonical\" href=\"https://example.com/notes\">\n\
+                    <script>track()</script></head>\n\
+                    <body>\n\
+                    <nav><a href=\"/\">Home</a></nav>\n\
+                    <p>Outside the article.</p>\n\
+                    <article>\n\
+                    <h1>Version 2</h1>\n\
+                    <p>Faster <b>indexing</b>.</p>\n\
+                    <div class=\"share-buttons\">Share this</div>\n\
+                    <h2 id=\"fixes\">Fixes</h2>\n\
+                    <p>Fewer crashes.</p>\n\
+                    </article>\n\
+                    <footer>Copyright</footer>\n\
+                    </body></html>";
+        let document = extract(page, true);
+
+        assert_eq!(document.title.as_deref(), Some("Release notes"));
+        assert_eq!(document.url.as_deref(), Some("https://example.com/notes"));
+        assert_eq!(
+            contents(&document),
+            [
+                (FragmentKind::Heading, "Version 2"),
+                (FragmentKind::Paragraph, "Faster indexing."),
+                (FragmentKind::Heading, "Fixes"),
+                (FragmentKind::Paragraph, "Fewer crashes."),
+            ]
+        );
+        assert_eq!(document.fragments[3].heading_path, ["Version 2", "Fixes"]);
+        assert_eq!(document.anchors, [("fixes".to_string(), 12)]);
+
+        let fixes = &document.fragments[3];
+        assert_eq!(fixes.range.start_line, 13);
+        assert_eq!(
+            &page[fixes.range.start_byte..fixes.range.end_byte],
+            "<p>Fewer crashes.</p>"
+        );
+    }
+
+    #[test]
+    fn chrome_is_kept_unless_stripping() {
+        let page = "<nav>Home</nav><p>Body</p><script>track()</script>";
+
+        assert_eq!(
+            contents(&extract(page, false)),
+            [(FragmentKind::Paragraph, "Home\nBody")]
+        );
+        assert_eq!(
+            contents(&extract(page, true)),
+            [(FragmentKind::Paragraph, "Body")]
+        );
+    }
+
+    #[test]
+    fn open_graph_stands_in_for_a_missing_title_and_url() {
+        let page = "<meta property=\"og:title\" content=\"Shared\">\n\
+                    <meta property=\"og:url\" content=\"https://example.com/s\">\n\
+                    <p>Text</p>";
+        let document = extract(page, true);
+
+        assert_eq!(document.title.as_deref(), Some("Shared"));
+        assert_eq!(document.url.as_deref(), Some("https://example.com/s"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use anyhow::Context;
+use mail_parser::{mailbox::mbox::MessageIterator, Message, MessageParser};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{lines_with_ranges, FileFragment, FragmentKind, Metadata, ParsedFile};
+
+/// The lines a mail client puts above a quoted reply, e.g. "On Tue, 2 Apr 2024
+/// at 10:01, Jane <jane@example.com> wrote:".
+static ATTRIBUTION_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(On\s.+|.+\swrote|-+\s*Original Message\s*-+):?$").unwrap());
+
+/// Whether a file is a message in a Maildir, i.e. it sits in the `cur/` or
+/// `new/` folder of a directory that also has a `tmp/` folder.
+pub fn is_maildir_message(path: &Path) -> bool {
+    let Some(folder) = path.parent() else {
+        return false;
+    };
+
+    let in_mail_folder = folder.file_name().is_some_and(|x| x == "cur" || x == "new");
+
+    in_mail_folder && folder.parent().is_some_and(|x| x.join("tmp").is_dir())
+}
+
+/// Drops what a reader would skip: quoted replies along with the attribution
+/// introducing them, and everything after the signature delimiter.
+fn strip_replies(body: &str) -> String {
+    let mut kept: Vec<&str> = vec![];
+
+    for line in body.lines() {
+        // Some clients trim the delimiter's trailing space.
+        if line.trim_end() == "--" {
+            break;
+        }
+
+        if !line.starts_with('>') {
+            kept.push(line);
+            continue;
+        }
+
+        // Attributions are often wrapped, so match against the whole
+        // paragraph directly above the quote.
+        let paragraph_start = kept
+            .iter()
+            .rposition(|x| x.trim().is_empty())
+            .map_or(0, |x| x + 1);
+        let paragraph = kept[paragraph_start..]
+            .iter()
+            .flat_map(|x| x.split_whitespace())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if ATTRIBUTION_REGEX.is_match(&paragraph) {
+            kept.truncate(paragraph_start);
+        }
+    }
+
+    kept.join("\n")
+}
+
+/// Splits a message's text body into paragraphs on blank lines, each headed
+/// by the message's subject.  Ranges are relative to the decoded body once
+/// replies are stripped.
+fn message_fragments(
+    message: &Message,
+    chapter: Option<String>,
+    metadata: Metadata,
+) -> Vec<FileFragment> {
+    let body = (0..message.text_body_count())
+        .filter_map(|x| message.body_text(x))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let body = strip_replies(&body);
+
+    let heading_path = message
+        .subject()
+        .map(|x| vec![x.to_string()])
+        .unwrap_or_default();
+
+    let mut fragments = vec![];
+    let mut current: Option<FileFragment> = None;
+
+    for (line, range) in lines_with_ranges(&body) {
+        if line.trim().is_empty() {
+            fragments.extend(current.take());
+            continue;
+        }
+
+        match current.as_mut() {
+            Some(fragment) => {
+                fragment.contents.push('\n');
+                fragment.contents.push_str(line);
+                fragment.range.end_line = range.end_line;
+                fragment.range.end_byte = range.end_byte;
+            }
+            None => {
+                current = Some(FileFragment {
+                    kind: FragmentKind::Paragraph,
+                    contents: line.to_string(),
+                    range,
+                    heading_path: heading_path.clone(),
+                    page: None,
+                    chapter: chapter.clone(),
+                    language: None,
+                    cell: None,
+                    metadata: metadata.clone(),
+                })
+            }
+        }
+    }
+
+    fragments.extend(current.take());
+    fragments
+}
+
+/// A message's subject, sender, date and ID.
+fn message_metadata(message: &Message) -> Metadata {
+    let sender = message.from().and_then(|x| x.first());
+    let headers = [
+        ("subject", message.subject().map(|x| x.to_string())),
+        (
+            "from",
+            sender.and_then(|x| x.address()).map(|x| x.to_string()),
+        ),
+        (
+            "from_name",
+            sender.and_then(|x| x.name()).map(|x| x.to_string()),
+        ),
+        ("date", message.date().map(|x| x.to_rfc3339())),
+        ("message_id", message.message_id().map(|x| x.to_string())),
+    ];
+
+    let mut metadata = Metadata::default();
+    for (key, value) in headers {
+        if let Some(value) = value {
+            metadata.insert(key, value);
+        }
+    }
+
+    metadata
+}
+
+/// Parses a single message, e.g. from a Maildir, recording its subject,
+/// sender, date and message ID as metadata.
+pub async fn parse_message(path: &Path) -> anyhow::Result<ParsedFile> {
+    let bytes = tokio::fs::read(path).await?;
+    let message = MessageParser::default()
+        .parse(&bytes)
+        .context("Could not parse message")?;
+
+    Ok(ParsedFile {
+        metadata: message_metadata(&message),
+        fragments: message_fragments(&message, None, Metadata::default()),
+        links: vec![],
+        nodes: vec![],
+    })
+}
+
+/// Parses an mbox file message by message.  Each message's fragments are
+/// located by its message ID and line within the message body, headed by its
+/// subject, and carry its headers as metadata.
+pub async fn parse_mbox(path: &Path) -> anyhow::Result<ParsedFile> {
+    let file = std::fs::File::open(path)?;
+
+    // Mailboxes can be large, so read and decode them off the async runtime.
+    let fragments = tokio::task::spawn_blocking(move || {
+        let parser = MessageParser::default();
+        let mut fragments = vec![];
+
+        for (index, entry) in MessageIterator::new(file).enumerate() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let Some(message) = parser.parse(entry.contents()) else {
+                continue;
+            };
+
+            let chapter = message
+                .message_id()
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| format!("message-{}", index + 1));
+            let metadata = message_metadata(&message);
+            fragments.extend(message_fragments(&message, Some(chapter), metadata));
+        }
+
+        fragments
+    })
+    .await?;
+
+    Ok(fragments.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_replies_drops_quotes_attributions_and_signatures() {
+        let body = "Sounds good.\n\
+                    \n\
+                    On Tue, 2 Apr 2024 at 10:01, Jane\n\
+                    <jane@example.com> wrote:\n\
+                    > Shall we meet on Thursday?\n\
+                    \n\
+                    See you then.\n\
+                    -- \n\
+                    Bob";
+
+        assert_eq!(strip_replies(body), "Sounds good.\n\n\nSee you then.");
+    }
+
+    #[test]
+    fn strip_replies_keeps_text_above_unattributed_quotes() {
+        let body = "As you said:\n> It works.\nAgreed.";
+
+        assert_eq!(strip_replies(body), "As you said:\nAgreed.");
+    }
+}
@@ -0,0 +1,290 @@
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub mod code;
+pub mod epub;
+pub mod html;
+pub mod mail;
+pub mod notebook;
+pub mod pdf;
+pub mod text;
+
+/// Where a fragment came from inside its file.  Lines are 1-indexed and
+/// inclusive, bytes are a half-open range into the raw file contents.  For
+/// formats whose text has to be extracted first (PDF, email), both are
+/// relative to the extracted text of the fragment's page or message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentRange {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub start_byte: usize,
+    pub end_byte: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentKind {
+    Heading,
+    Paragraph,
+}
+
+impl FragmentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FragmentKind::Heading => "heading",
+            FragmentKind::Paragraph => "paragraph",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileFragment {
+    pub kind: FragmentKind,
+    pub contents: String,
+    pub range: FragmentRange,
+
+    /// The headings enclosing the fragment, outermost first.  A heading's own
+    /// path ends with itself.
+    pub heading_path: Vec<String>,
+
+    /// The 1-indexed page the fragment is on, for paginated formats.
+    pub page: Option<u32>,
+
+    /// The chapter the fragment is in, for formats made of several documents:
+    /// the chapter's path inside an EPUB, or a message's ID in an mbox.
+    pub chapter: Option<String>,
+
+    /// The programming language of the fragment, for source code.
+    pub language: Option<String>,
+
+    /// The 1-indexed cell the fragment is in, for notebooks.  The fragment's
+    /// range is relative to the cell's source.
+    pub cell: Option<u32>,
+
+    /// Metadata that applies to just this fragment, such as the sender of a
+    /// message in an mbox.  Stored along with its file's metadata, which it
+    /// overrides.
+    pub metadata: Metadata,
+}
+
+/// Key/value metadata recorded by parsers, e.g. a page's title or a
+/// message's date.  Most keys hold a single value, but some (tags) hold
+/// several.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata(BTreeMap<String, Vec<String>>);
+
+impl Metadata {
+    /// Sets a key to a single value, replacing any it had.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.0.insert(key.into(), vec![value.into()]);
+    }
+
+    /// Adds a value to a key, keeping any it had.
+    pub fn push(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let values = self.0.entry(key.into()).or_default();
+        let value = value.into();
+
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[String]> {
+        self.0.get(key).map(|x| x.as_slice())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    /// Every key/value pair, with keys holding several values repeated.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .flat_map(|(key, values)| values.iter().map(move |x| (key.as_str(), x.as_str())))
+    }
+
+    /// Returns this metadata with `other`'s keys laid over it.
+    pub fn merged(&self, other: &Metadata) -> Metadata {
+        let mut merged = self.clone();
+        merged.0.extend(
+            other
+                .0
+                .iter()
+                .map(|(key, values)| (key.clone(), values.clone())),
+        );
+        merged
+    }
+
+    /// The metadata as a JSON object of arrays, which is how it is stored
+    /// alongside each embedding.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self.0)
+    }
+}
+
+/// Settings that change how files are parsed, read from the config file.
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    /// Embed the text output of notebook code cells along with their source.
+    pub notebook_outputs: bool,
+}
+
+impl ParseOptions {
+    /// Mixes the options that change how `path` is parsed into the hash of
+    /// its contents, so that changing them in the config reindexes the file.
+    /// Files parsed with the default options keep their plain content hash.
+    pub fn stamp_hash(&self, path: &Path, hash: Vec<u8>) -> Vec<u8> {
+        let is_notebook = path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("ipynb"));
+        if !(is_notebook && self.notebook_outputs) {
+            return hash;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(&hash);
+        hasher.update(b"notebook_outputs");
+        hasher.finalize().to_vec()
+    }
+}
+
+/// Tracks the headings enclosing the current position in a document, given
+/// headings and their nesting levels in document order.
+#[derive(Debug, Default)]
+pub struct HeadingStack(Vec<(usize, String)>);
+
+impl HeadingStack {
+    /// Enters a heading, closing any open headings at the same or a deeper
+    /// level.
+    pub fn push(&mut self, level: usize, heading: String) {
+        while self.0.last().is_some_and(|(x, _)| *x >= level) {
+            self.0.pop();
+        }
+
+        self.0.push((level, heading));
+    }
+
+    pub fn path(&self) -> Vec<String> {
+        self.0.iter().map(|(_, x)| x.to_string()).collect()
+    }
+}
+
+/// What a link points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// Another file, resolved against the directory of the file linking to
+    /// it.
+    File(PathBuf),
+
+    /// An Org ID, e.g. `[[id:...]]`.
+    Id(String),
+}
+
+/// A link from one of a file's fragments to another file or note.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// The first line of the fragment the link is in.
+    pub fragment_line: usize,
+    pub target: LinkTarget,
+
+    /// The link's text, if it has any besides its target.
+    pub description: Option<String>,
+}
+
+/// An Org node: a heading, or a whole file, with an `:ID:` that links can
+/// point at.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+
+    /// The line of the node's heading, or `None` if the node is the file.
+    pub line: Option<usize>,
+    pub title: Option<String>,
+}
+
+/// The result of parsing a file: the fragments to embed, plus whatever
+/// file-level metadata the format carries (an HTML page's title, say), the
+/// links it makes to other files and the Org nodes it defines.
+#[derive(Debug, Default)]
+pub struct ParsedFile {
+    pub metadata: Metadata,
+    pub fragments: Vec<FileFragment>,
+    pub links: Vec<Link>,
+    pub nodes: Vec<Node>,
+}
+
+impl From<Vec<FileFragment>> for ParsedFile {
+    fn from(fragments: Vec<FileFragment>) -> Self {
+        ParsedFile {
+            fragments,
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits text into lines, pairing each with the range it covers.  Line
+/// endings are excluded from both the line and its byte range.
+pub fn lines_with_ranges(text: &str) -> impl Iterator<Item = (&str, FragmentRange)> {
+    text.split_inclusive('\n')
+        .enumerate()
+        .scan(0, |offset, (index, raw_line)| {
+            let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
+            let range = FragmentRange {
+                start_line: index + 1,
+                end_line: index + 1,
+                start_byte: *offset,
+                end_byte: *offset + line.len(),
+            };
+            *offset += raw_line.len();
+
+            Some((line, range))
+        })
+}
+
+/// Parses a file into fragments for embedding, picking a parser based on the
+/// file's extension.  Anything unrecognized is treated as plain text.
+/// The kind of source a file is recorded as (`org`, `pdf`, `mail`, ...) in
+/// the `source` metadata key.
+pub async fn parse_file(path: &Path, options: &ParseOptions) -> anyhow::Result<ParsedFile> {
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+
+    let (source, mut parsed) = match extension.as_deref() {
+        // Maildir messages are named by the delivering agent and carry no
+        // meaningful extension.
+        _ if mail::is_maildir_message(path) => ("mail", mail::parse_message(path).await?),
+        Some(extension) if code::is_supported(extension) => {
+            ("code", code::parse(path, extension).await?)
+        }
+        Some("epub") => ("epub", epub::parse(path).await?),
+        Some("html" | "htm" | "xhtml") => ("html", html::parse(path).await?),
+        Some("ipynb") => ("notebook", notebook::parse(path, options).await?),
+        Some("mbox") => ("mbox", mail::parse_mbox(path).await?),
+        Some("eml") => ("mail", mail::parse_message(path).await?),
+        Some("pdf") => ("pdf", pdf::parse(path).await?),
+        Some("org") => ("org", text::parse(path).await?),
+        Some("md" | "markdown") => ("markdown", text::parse(path).await?),
+        // Anything else that isn't text, like an image, has nothing to
+        // embed.  It's stored without fragments rather than queued to be
+        // retried, which would fail the same way.
+        _ => match text::parse(path).await {
+            Err(error) if is_not_text(&error) => ("text", ParsedFile::default()),
+            parsed => ("text", parsed?),
+        },
+    };
+
+    parsed.metadata.insert("source", source);
+    Ok(parsed)
+}
+
+/// Whether reading a file as text failed because it isn't valid UTF-8.
+fn is_not_text(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|x| x.kind() == std::io::ErrorKind::InvalidData)
+}
@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+use super::{
+    lines_with_ranges, FileFragment, FragmentKind, FragmentRange, HeadingStack, Metadata,
+    ParseOptions, ParsedFile,
+};
+
+static MARKDOWN_HEADING_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(#{1,6})\s+(.+?)\s*#*\s*$").unwrap());
+
+/// Output MIME types worth embedding.  Images come through as base64 and
+/// HTML duplicates `text/plain`, so neither is.
+const TEXT_OUTPUT_TYPES: &[&str] = &["text/plain", "text/markdown"];
+
+/// Notebook text fields are either a string or a list of lines.
+fn text_field(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.to_string(),
+        Value::Array(lines) => lines.iter().filter_map(|x| x.as_str()).collect(),
+        _ => String::new(),
+    }
+}
+
+fn cell_outputs(cell: &Value) -> String {
+    let outputs = cell["outputs"].as_array().into_iter().flatten();
+
+    outputs
+        .filter_map(|output| match output["output_type"].as_str()? {
+            "stream" => Some(text_field(&output["text"])),
+            "execute_result" | "display_data" => TEXT_OUTPUT_TYPES
+                .iter()
+                .map(|x| text_field(&output["data"][x]))
+                .find(|x| !x.is_empty()),
+            "error" => Some(format!(
+                "{}: {}",
+                output["ename"].as_str().unwrap_or_default(),
+                output["evalue"].as_str().unwrap_or_default()
+            )),
+            _ => None,
+        })
+        .map(|x| x.trim_end().to_string())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The range text covers, once trailing whitespace is trimmed.
+fn text_range(text: &str) -> Option<FragmentRange> {
+    lines_with_ranges(text.trim_end())
+        .map(|(_, range)| range)
+        .reduce(|first, last| FragmentRange {
+            end_line: last.end_line,
+            end_byte: last.end_byte,
+            ..first
+        })
+}
+
+struct NotebookBuilder {
+    language: Option<String>,
+    headings: HeadingStack,
+    fragments: Vec<FileFragment>,
+}
+
+impl NotebookBuilder {
+    fn push(&mut self, kind: FragmentKind, cell: u32, text: &str, language: Option<String>) {
+        if let Some(range) = text_range(text) {
+            self.push_with_range(kind, cell, text, range, language);
+        }
+    }
+
+    fn push_with_range(
+        &mut self,
+        kind: FragmentKind,
+        cell: u32,
+        text: &str,
+        range: FragmentRange,
+        language: Option<String>,
+    ) {
+        self.fragments.push(FileFragment {
+            kind,
+            contents: text.trim_end().to_string(),
+            range,
+            heading_path: self.headings.path(),
+            page: None,
+            chapter: None,
+            language,
+            cell: Some(cell),
+            metadata: Metadata::default(),
+        });
+    }
+
+    /// Markdown cells are split like an Org file: headings become their own
+    /// fragments and open a new level of the heading path, and runs of
+    /// non-blank lines between them are folded into paragraphs.
+    fn push_markdown(&mut self, cell: u32, source: &str) {
+        let mut in_fence = false;
+        let mut paragraph: Option<FileFragment> = None;
+
+        for (line, range) in lines_with_ranges(source) {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+            }
+
+            let heading = (!in_fence)
+                .then(|| MARKDOWN_HEADING_REGEX.captures(line))
+                .flatten();
+
+            if let Some(captures) = heading {
+                self.fragments.extend(paragraph.take());
+                self.headings
+                    .push(captures[1].len(), captures[2].to_string());
+                self.fragments.push(FileFragment {
+                    kind: FragmentKind::Heading,
+                    contents: line.to_string(),
+                    range,
+                    heading_path: self.headings.path(),
+                    page: None,
+                    chapter: None,
+                    language: None,
+                    cell: Some(cell),
+                    metadata: Metadata::default(),
+                });
+                continue;
+            }
+
+            if line.trim().is_empty() && !in_fence {
+                self.fragments.extend(paragraph.take());
+                continue;
+            }
+
+            match paragraph.as_mut() {
+                Some(fragment) => {
+                    fragment.contents.push('\n');
+                    fragment.contents.push_str(line);
+                    fragment.range.end_line = range.end_line;
+                    fragment.range.end_byte = range.end_byte;
+                }
+                None => {
+                    paragraph = Some(FileFragment {
+                        kind: FragmentKind::Paragraph,
+                        contents: line.to_string(),
+                        range,
+                        heading_path: self.headings.path(),
+                        page: None,
+                        chapter: None,
+                        language: None,
+                        cell: Some(cell),
+                        metadata: Metadata::default(),
+                    })
+                }
+            }
+        }
+
+        self.fragments.extend(paragraph.take());
+    }
+}
+
+/// Parses a Jupyter notebook cell by cell.  Markdown headings give the
+/// notebook its heading structure, code cells are tagged with the kernel's
+/// language, and, if enabled, their text outputs are embedded alongside them.
+/// Fragments are located by cell number and line within the cell.
+pub async fn parse(path: &Path, options: &ParseOptions) -> anyhow::Result<ParsedFile> {
+    let notebook: Value = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+
+    let language = notebook["metadata"]["language_info"]["name"]
+        .as_str()
+        .or(notebook["metadata"]["kernelspec"]["language"].as_str())
+        .map(|x| x.to_lowercase());
+
+    let mut builder = NotebookBuilder {
+        language: language.clone(),
+        headings: HeadingStack::default(),
+        fragments: vec![],
+    };
+
+    for (index, cell) in notebook["cells"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let cell_number = index as u32 + 1;
+        let source = text_field(&cell["source"]);
+
+        match cell["cell_type"].as_str() {
+            Some("markdown") => builder.push_markdown(cell_number, &source),
+            Some("code") => {
+                if !source.trim().is_empty() {
+                    let language = builder.language.clone();
+                    builder.push(FragmentKind::Paragraph, cell_number, &source, language);
+                }
+
+                // Outputs aren't lines of the notebook, so they point at the
+                // source that produced them.
+                if options.notebook_outputs {
+                    let outputs = cell_outputs(cell);
+                    if !outputs.is_empty() {
+                        let range = text_range(&source).unwrap_or(FragmentRange {
+                            start_line: 1,
+                            end_line: 1,
+                            start_byte: 0,
+                            end_byte: 0,
+                        });
+                        builder.push_with_range(
+                            FragmentKind::Paragraph,
+                            cell_number,
+                            &outputs,
+                            range,
+                            None,
+                        );
+                    }
+                }
+            }
+            _ => {
+                if !source.trim().is_empty() {
+                    builder.push(FragmentKind::Paragraph, cell_number, &source, None);
+                }
+            }
+        }
+    }
+
+    let mut parsed = ParsedFile::from(builder.fragments);
+    if let Some(language) = language {
+        parsed.metadata.insert("language", language);
+    }
+
+    Ok(parsed)
+}
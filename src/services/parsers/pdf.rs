@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::{lines_with_ranges, FileFragment, FragmentKind, Metadata, ParsedFile};
+
+/// Extracts the text of each page of a PDF and splits it into paragraphs on
+/// blank lines.  PDFs carry no reliable heading structure, so every fragment
+/// is a paragraph located by its page number.
+pub async fn parse(path: &Path) -> anyhow::Result<ParsedFile> {
+    let bytes = tokio::fs::read(path).await?;
+
+    // Extraction is CPU bound and pdf-extract is known to panic on malformed
+    // documents, so keep it off the async runtime and contain any panic.
+    let pages =
+        tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem_by_pages(&bytes))
+            .await
+            .context("PDF extraction panicked")??;
+
+    let mut fragments = vec![];
+
+    for (index, page_text) in pages.iter().enumerate() {
+        let page = Some(index as u32 + 1);
+        let mut current: Option<FileFragment> = None;
+
+        for (line, range) in lines_with_ranges(page_text) {
+            let line = line.trim();
+
+            if line.is_empty() {
+                fragments.extend(current.take());
+                continue;
+            }
+
+            match current.as_mut() {
+                Some(fragment) => {
+                    fragment.contents.push('\n');
+                    fragment.contents.push_str(line);
+                    fragment.range.end_line = range.end_line;
+                    fragment.range.end_byte = range.end_byte;
+                }
+                None => {
+                    current = Some(FileFragment {
+                        kind: FragmentKind::Paragraph,
+                        contents: line.to_string(),
+                        range,
+                        heading_path: vec![],
+                        page,
+                        chapter: None,
+                        language: None,
+                        cell: None,
+                        metadata: Metadata::default(),
+                    })
+                }
+            }
+        }
+
+        fragments.extend(current.take());
+    }
+
+    Ok(fragments.into())
+}
@@ -0,0 +1,640 @@
+use std::path::{Component, Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::platform::home_dir;
+
+use super::{
+    lines_with_ranges, FileFragment, FragmentKind, HeadingStack, Link, LinkTarget, Metadata, Node,
+    ParsedFile,
+};
+
+static HEADING_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\*+)\s+(.+)$").unwrap());
+
+/// An Org heading's title, split from its priority cookie and trailing tags,
+/// e.g. `[#A] Write report :work:writing:`.
+static HEADING_TITLE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:\[#[A-Za-z0-9]\]\s+)?(.*?)(?:\s+(:(?:[\w@#%]+:)+))?\s*$").unwrap()
+});
+
+/// An Org in-buffer setting, e.g. `#+TITLE: Notes`.
+static KEYWORD_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^#\+(title|author|date|filetags|todo|seq_todo|typ_todo):\s*(.*?)\s*$").unwrap()
+});
+
+/// A planning line directly below a heading, e.g. `SCHEDULED: <2024-04-02 Tue>`.
+static PLANNING_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(SCHEDULED|DEADLINE):\s*<(\d{4}-\d{2}-\d{2})").unwrap());
+
+/// The `:ID:` property in a property drawer, as set by org-roam.
+static ID_PROPERTY_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^:ID:\s+(\S+)$").unwrap());
+
+static DATE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap());
+
+/// An Org link, e.g. `[[file:notes.org][Notes]]` or `[[id:...]]`.
+static ORG_LINK_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[\[([^\[\]]+)\](?:\[([^\[\]]+)\])?\]").unwrap());
+
+/// A Markdown inline link, e.g. `[Notes](notes.md "title")`.
+static MARKDOWN_LINK_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\[([^\[\]]*)\]\(<?([^()\s<>]+)>?(?:\s+"[^"]*")?\)"#).unwrap());
+
+/// The scheme of a URL-like link target, e.g. `https:` or `mailto:`.
+static SCHEME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap());
+
+/// The TODO keywords used when a file doesn't declare its own with `#+TODO:`.
+const DEFAULT_OPEN_KEYWORDS: &[&str] = &["TODO", "NEXT", "STARTED", "WAITING", "HOLD"];
+const DEFAULT_DONE_KEYWORDS: &[&str] = &["DONE", "CANCELLED", "CANCELED"];
+
+/// The TODO keywords in effect for a file, split into those that mark an open
+/// item and those that mark a finished one.
+struct TodoKeywords {
+    open: Vec<String>,
+    done: Vec<String>,
+}
+
+impl Default for TodoKeywords {
+    fn default() -> Self {
+        TodoKeywords {
+            open: DEFAULT_OPEN_KEYWORDS
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            done: DEFAULT_DONE_KEYWORDS
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl TodoKeywords {
+    /// Reads a `#+TODO:` declaration, e.g. `TODO NEXT(n) | DONE(d!)`.  Without
+    /// a `|`, the last keyword is the only finished one.
+    fn parse(declaration: &str) -> Self {
+        let keywords = |x: &str| {
+            x.split_whitespace()
+                .map(|x| x.split('(').next().unwrap_or_default().to_string())
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        match declaration.split_once('|') {
+            Some((open, done)) => TodoKeywords {
+                open: keywords(open),
+                done: keywords(done),
+            },
+            None => {
+                let mut open = keywords(declaration);
+                let done = open.pop().into_iter().collect();
+                TodoKeywords { open, done }
+            }
+        }
+    }
+
+    /// Splits a heading's leading TODO keyword from the rest of it, returning
+    /// the keyword and whether it marks an open item.
+    fn split<'a>(&self, heading: &'a str) -> (Option<(&'a str, bool)>, &'a str) {
+        let (first, rest) = heading
+            .split_once(char::is_whitespace)
+            .unwrap_or((heading, ""));
+
+        if self.open.iter().any(|x| x == first) {
+            (Some((first, true)), rest.trim_start())
+        } else if self.done.iter().any(|x| x == first) {
+            (Some((first, false)), rest.trim_start())
+        } else {
+            (None, heading)
+        }
+    }
+}
+
+/// Records the title, author, date and tags an Org file declares about
+/// itself.  Dates are reduced to `YYYY-MM-DD` so they compare as text.
+fn record_keyword(metadata: &mut Metadata, keyword: &str, value: &str) {
+    match keyword {
+        "filetags" => {
+            for tag in value.split(':').filter(|x| !x.trim().is_empty()) {
+                metadata.push("tag", tag.trim());
+            }
+        }
+        "date" => {
+            if let Some(date) = DATE_REGEX.find(value) {
+                metadata.insert("date", date.as_str());
+            }
+        }
+        keyword if !value.is_empty() => metadata.insert(keyword, value),
+        _ => {}
+    }
+}
+
+/// Resolves a link's path against the directory of the file it is in,
+/// without touching the filesystem: the target may well not exist.
+fn resolve_link_path(path: &Path, target: &str) -> PathBuf {
+    let target = match target.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest),
+        None => path.parent().unwrap_or(Path::new("")).join(target),
+    };
+
+    let mut resolved = PathBuf::new();
+    for component in target.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+
+    resolved
+}
+
+/// Reads the target of an Org link.  Only `file:` and `id:` links, and bare
+/// paths, point at other notes; links to headings in the same file, URLs and
+/// the like are skipped.
+fn org_link_target(path: &Path, target: &str) -> Option<LinkTarget> {
+    if let Some(id) = target.strip_prefix("id:") {
+        return Some(LinkTarget::Id(id.trim().to_string()));
+    }
+
+    let file = match target.strip_prefix("file:") {
+        Some(file) => file,
+        None if ["/", "./", "../", "~/"]
+            .iter()
+            .any(|x| target.starts_with(x)) =>
+        {
+            target
+        }
+        None => return None,
+    };
+
+    // A search option, e.g. `file:notes.org::*Heading`, points into the file.
+    let file = file.split("::").next().unwrap_or_default();
+    if file.is_empty() {
+        return None;
+    }
+
+    Some(LinkTarget::File(resolve_link_path(path, file)))
+}
+
+/// Reads the target of a Markdown link, which points at another note unless
+/// it is a URL or an anchor in the same file.
+fn markdown_link_target(path: &Path, target: &str) -> Option<LinkTarget> {
+    if SCHEME_REGEX.is_match(target) {
+        return None;
+    }
+
+    let file = target.split('#').next().unwrap_or_default();
+    if file.is_empty() {
+        return None;
+    }
+
+    Some(LinkTarget::File(resolve_link_path(path, file)))
+}
+
+/// Finds the Org and Markdown links on a line, which is part of the fragment
+/// starting at `fragment_line`.
+fn line_links(path: &Path, line: &str, fragment_line: usize) -> Vec<Link> {
+    let org_links = ORG_LINK_REGEX.captures_iter(line).filter_map(|captures| {
+        let target = org_link_target(path, &captures[1])?;
+        Some((target, captures.get(2)))
+    });
+    let markdown_links = MARKDOWN_LINK_REGEX
+        .captures_iter(line)
+        .filter_map(|captures| {
+            let target = markdown_link_target(path, &captures[2])?;
+            Some((target, captures.get(1)))
+        });
+
+    org_links
+        .chain(markdown_links)
+        .map(|(target, description)| Link {
+            fragment_line,
+            target,
+            description: description
+                .map(|x| x.as_str().trim().to_string())
+                .filter(|x| !x.is_empty()),
+        })
+        .collect()
+}
+
+/// Parses an Org or Markdown-ish text file: every heading line becomes its own
+/// fragment, and the lines between headings are folded into paragraphs.  Org
+/// settings such as `#+TITLE:` are recorded as metadata.
+///
+/// Fragments also carry the metadata of the heading they are under: its tags
+/// (along with those of its parents and the file's `#+FILETAGS`), its TODO
+/// keyword (`todo`) and whether that marks it `open` or `done`
+/// (`todo_state`), and its `scheduled` and `deadline` dates.
+///
+/// An `:ID:` in a property drawer makes the heading above it, or the whole
+/// file when it comes before any heading, an Org node.  Fragments record the
+/// ID of the node they are in as `node_id`.  Drawers themselves aren't
+/// embedded.
+///
+/// Org and Markdown links to other files and to Org IDs are collected along
+/// with the fragment they are in.
+pub async fn parse(path: &Path) -> anyhow::Result<ParsedFile> {
+    let file_contents = tokio::fs::read_to_string(path).await?;
+
+    Ok(parse_text(path, &file_contents))
+}
+
+/// Parses the contents of the text file at `path`, which links are resolved
+/// against.
+fn parse_text(path: &Path, file_contents: &str) -> ParsedFile {
+    let mut fragments: Vec<FileFragment> = vec![];
+    let mut links = vec![];
+    let mut nodes: Vec<Node> = vec![];
+    let mut headings = HeadingStack::default();
+    let mut metadata = Metadata::default();
+    let mut todo_keywords = TodoKeywords::default();
+
+    // The tags declared by each enclosing heading, by level, and the metadata
+    // of the heading the current line is under.
+    let mut heading_tags: Vec<(usize, Vec<String>)> = vec![];
+    let mut item = Metadata::default();
+
+    // The IDs of the enclosing nodes, by level, with the file's own at level
+    // 0.  `item_start` is the index of the current heading's fragment, and
+    // `heading` its level and line.
+    let mut node_ids: Vec<(usize, String)> = vec![];
+    let mut item_start = 0;
+    let mut heading: Option<(usize, usize)> = None;
+    let mut in_drawer = false;
+
+    for (line, range) in lines_with_ranges(file_contents) {
+        if line.trim().eq_ignore_ascii_case(":PROPERTIES:") {
+            in_drawer = true;
+            continue;
+        }
+        if in_drawer {
+            if line.trim().eq_ignore_ascii_case(":END:") {
+                in_drawer = false;
+            } else if let Some(captures) = ID_PROPERTY_REGEX.captures(line.trim()) {
+                let id = captures[1].to_string();
+                node_ids.push((heading.map_or(0, |(level, _)| level), id.to_string()));
+                nodes.push(Node {
+                    id: id.to_string(),
+                    line: heading.map(|(_, line)| line),
+                    title: heading.and_then(|_| headings.path().pop()),
+                });
+
+                item.insert("node_id", id);
+                for fragment in &mut fragments[item_start..] {
+                    fragment.metadata = item.clone();
+                }
+            }
+            continue;
+        }
+
+        if let Some(captures) = KEYWORD_REGEX.captures(line) {
+            match captures[1].to_lowercase().as_str() {
+                "todo" | "seq_todo" | "typ_todo" => {
+                    todo_keywords = TodoKeywords::parse(&captures[2])
+                }
+                keyword => record_keyword(&mut metadata, keyword, &captures[2]),
+            }
+        }
+
+        let fragment = if let Some(captures) = HEADING_REGEX.captures(line) {
+            let level = captures[1].len();
+            let (todo, rest) = todo_keywords.split(captures[2].trim());
+
+            let title = HEADING_TITLE_REGEX.captures(rest);
+            let own_tags = title
+                .as_ref()
+                .and_then(|x| x.get(2))
+                .map(|x| {
+                    x.as_str()
+                        .split(':')
+                        .filter(|x| !x.is_empty())
+                        .map(|x| x.to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let title = title
+                .as_ref()
+                .and_then(|x| x.get(1))
+                .map_or(rest, |x| x.as_str());
+
+            headings.push(level, title.to_string());
+            while heading_tags.last().is_some_and(|(x, _)| *x >= level) {
+                heading_tags.pop();
+            }
+            heading_tags.push((level, own_tags));
+            while node_ids.last().is_some_and(|(x, _)| *x >= level) {
+                node_ids.pop();
+            }
+            item_start = fragments.len();
+            heading = Some((level, range.start_line));
+
+            item = Metadata::default();
+            if let Some((_, id)) = node_ids.last() {
+                item.insert("node_id", id);
+            }
+            let file_tags = metadata.get("tag").unwrap_or_default();
+            for tag in file_tags
+                .iter()
+                .chain(heading_tags.iter().flat_map(|(_, x)| x))
+            {
+                item.push("tag", tag.to_string());
+            }
+            if let Some((keyword, open)) = todo {
+                item.insert("todo", keyword);
+                item.insert("todo_state", if open { "open" } else { "done" });
+            }
+
+            FileFragment {
+                kind: FragmentKind::Heading,
+                contents: captures[0].to_string(),
+                range,
+                heading_path: headings.path(),
+                page: None,
+                chapter: None,
+                language: None,
+                cell: None,
+                metadata: item.clone(),
+            }
+        } else {
+            // A planning line belongs to the heading directly above it.
+            let follows_heading = fragments.last().is_some_and(|x| {
+                x.kind == FragmentKind::Heading && x.range.end_line + 1 == range.start_line
+            });
+            if follows_heading && PLANNING_REGEX.is_match(line) {
+                for captures in PLANNING_REGEX.captures_iter(line) {
+                    item.insert(captures[1].to_lowercase(), &captures[2]);
+                }
+                if let Some(heading) = fragments.last_mut() {
+                    heading.metadata = item.clone();
+                }
+            }
+
+            FileFragment {
+                kind: FragmentKind::Paragraph,
+                contents: line.to_string(),
+                range,
+                heading_path: headings.path(),
+                page: None,
+                chapter: None,
+                language: None,
+                cell: None,
+                metadata: item.clone(),
+            }
+        };
+
+        // Consecutive paragraph lines are folded into a single fragment
+        // spanning all of them.
+        match fragments.last_mut() {
+            Some(last)
+                if last.kind == FragmentKind::Paragraph
+                    && fragment.kind == FragmentKind::Paragraph =>
+            {
+                last.contents.push('\n');
+                last.contents.push_str(&fragment.contents);
+                last.range.end_line = fragment.range.end_line;
+                last.range.end_byte = fragment.range.end_byte;
+            }
+            _ => fragments.push(fragment),
+        }
+
+        if let Some(fragment) = fragments.last() {
+            links.extend(line_links(path, line, fragment.range.start_line));
+        }
+    }
+
+    // A file's title often comes after its property drawer.
+    for node in nodes.iter_mut().filter(|x| x.line.is_none()) {
+        node.title = metadata.get("title").and_then(|x| x.first()).cloned();
+    }
+
+    ParsedFile {
+        metadata,
+        fragments,
+        links,
+        nodes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_org(text: &str) -> ParsedFile {
+        parse_text(Path::new("/notes/todo.org"), text)
+    }
+
+    fn fragment<'a>(parsed: &'a ParsedFile, contents: &str) -> &'a FileFragment {
+        parsed
+            .fragments
+            .iter()
+            .find(|x| x.contents == contents)
+            .unwrap()
+    }
+
+    fn values<'a>(fragment: &'a FileFragment, key: &str) -> Vec<&'a str> {
+        fragment
+            .metadata
+            .get(key)
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn headings_inherit_file_and_parent_tags() {
+        let parsed = parse_org(
+            "#+FILETAGS: :notes:\n\
+             * Write report :work:\n\
+             ** Draft outline :writing:\n\
+             Some text.\n\
+             * Ship it\n",
+        );
+
+        let outline = fragment(&parsed, "** Draft outline :writing:");
+        assert_eq!(outline.heading_path, ["Write report", "Draft outline"]);
+        assert_eq!(values(outline, "tag"), ["notes", "work", "writing"]);
+        assert_eq!(
+            values(fragment(&parsed, "Some text."), "tag"),
+            ["notes", "work", "writing"]
+        );
+        assert_eq!(values(fragment(&parsed, "* Ship it"), "tag"), ["notes"]);
+    }
+
+    #[test]
+    fn headings_record_todo_keywords_and_planning() {
+        let parsed = parse_org(
+            "* TODO [#A] Write report\n\
+             SCHEDULED: <2024-04-02 Tue> DEADLINE: <2024-04-05 Fri>\n\
+             * CANCELLED Old plan\n\
+             * TODOS to think about\n",
+        );
+
+        let report = fragment(&parsed, "* TODO [#A] Write report");
+        assert_eq!(report.heading_path, ["Write report"]);
+        assert_eq!(values(report, "todo"), ["TODO"]);
+        assert_eq!(values(report, "todo_state"), ["open"]);
+        assert_eq!(values(report, "scheduled"), ["2024-04-02"]);
+        assert_eq!(values(report, "deadline"), ["2024-04-05"]);
+
+        let plan = fragment(&parsed, "* CANCELLED Old plan");
+        assert_eq!(values(plan, "todo_state"), ["done"]);
+
+        let todos = fragment(&parsed, "* TODOS to think about");
+        assert!(values(todos, "todo").is_empty());
+    }
+
+    #[test]
+    fn files_can_declare_their_own_todo_keywords() {
+        let parsed = parse_org(
+            "#+TODO: IDEA DRAFT(d) | PUBLISHED(p!)\n\
+             * DRAFT Post\n\
+             * PUBLISHED Older post\n\
+             * TODO Not a keyword here\n",
+        );
+
+        assert_eq!(
+            values(fragment(&parsed, "* DRAFT Post"), "todo_state"),
+            ["open"]
+        );
+        assert_eq!(
+            values(fragment(&parsed, "* PUBLISHED Older post"), "todo_state"),
+            ["done"]
+        );
+        assert!(values(fragment(&parsed, "* TODO Not a keyword here"), "todo").is_empty());
+    }
+
+    #[test]
+    fn todo_declarations_without_a_separator_finish_with_the_last_keyword() {
+        let keywords = TodoKeywords::parse("TODO WAIT DONE");
+
+        assert_eq!(keywords.open, ["TODO", "WAIT"]);
+        assert_eq!(keywords.done, ["DONE"]);
+    }
+
+    #[test]
+    fn file_keywords_are_recorded_as_metadata() {
+        let parsed = parse_org("#+TITLE: Reading list\n#+DATE: <2024-03-01 Fri>\n");
+
+        assert_eq!(
+            parsed.metadata.get("title"),
+            Some(&["Reading list".to_string()][..])
+        );
+        assert_eq!(
+            parsed.metadata.get("date"),
+            Some(&["2024-03-01".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn org_links_to_files_are_resolved_against_the_linking_file() {
+        let parsed = parse_org(
+            "* Plans\n\
+             See [[file:../projects/plan.org::*Goals][the plan]] and [[./ideas.org]],\n\
+             not [[https://example.com][the site]] or [[*Plans]].\n",
+        );
+
+        let targets = parsed
+            .links
+            .iter()
+            .map(|x| (x.fragment_line, &x.target, x.description.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                (
+                    2,
+                    &LinkTarget::File(PathBuf::from("/projects/plan.org")),
+                    Some("the plan")
+                ),
+                (
+                    2,
+                    &LinkTarget::File(PathBuf::from("/notes/ideas.org")),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_links_skip_urls_and_anchors() {
+        let parsed = parse_text(
+            Path::new("/notes/readme.md"),
+            "Read [the intro](./guide.md#intro \"Guide\"), [the site](https://example.com) \
+             and [below](#usage).\n",
+        );
+
+        assert_eq!(parsed.links.len(), 1);
+        assert_eq!(
+            parsed.links[0].target,
+            LinkTarget::File(PathBuf::from("/notes/guide.md"))
+        );
+        assert_eq!(parsed.links[0].description.as_deref(), Some("the intro"));
+    }
+
+    #[test]
+    fn home_relative_links_are_resolved_against_the_home_directory() {
+        let parsed = parse_org("[[~/inbox.org]]\n");
+
+        assert_eq!(
+            parsed.links[0].target,
+            LinkTarget::File(home_dir().join("inbox.org"))
+        );
+    }
+
+    #[test]
+    fn property_drawer_ids_make_org_nodes() {
+        let parsed = parse_org(
+            ":PROPERTIES:\n\
+             :ID: inbox-id\n\
+             :END:\n\
+             #+TITLE: Inbox\n\
+             * Task\n\
+             :PROPERTIES:\n\
+             :ID: task-id\n\
+             :END:\n\
+             Body\n\
+             ** Subtask\n\
+             * Other\n",
+        );
+
+        let nodes = parsed
+            .nodes
+            .iter()
+            .map(|x| (x.id.as_str(), x.line, x.title.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nodes,
+            [
+                ("inbox-id", None, Some("Inbox")),
+                ("task-id", Some(5), Some("Task"))
+            ]
+        );
+
+        assert_eq!(values(fragment(&parsed, "* Task"), "node_id"), ["task-id"]);
+        assert_eq!(values(fragment(&parsed, "Body"), "node_id"), ["task-id"]);
+        assert_eq!(
+            values(fragment(&parsed, "** Subtask"), "node_id"),
+            ["task-id"]
+        );
+        assert_eq!(
+            values(fragment(&parsed, "* Other"), "node_id"),
+            ["inbox-id"]
+        );
+        assert!(!parsed.fragments.iter().any(|x| x.contents.contains(":ID:")));
+    }
+
+    #[test]
+    fn id_links_point_at_org_nodes() {
+        let parsed = parse_org("See [[id:task-id][the task]].\n");
+
+        assert_eq!(
+            parsed.links[0].target,
+            LinkTarget::Id("task-id".to_string())
+        );
+        assert_eq!(parsed.links[0].description.as_deref(), Some("the task"));
+    }
+}
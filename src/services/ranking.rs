@@ -0,0 +1,121 @@
+/// The cosine similarity of two vectors, or 0 if either is all zeroes.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Picks `count` of the candidates by maximal marginal relevance: one at a
+/// time, the candidate most similar to the query once its similarity to the
+/// closest candidate already picked is taken off.  `diversity` runs from 0,
+/// which ranks by relevance alone, to 1, which ranks by novelty alone.
+pub fn maximal_marginal_relevance<T>(
+    query: &[f32],
+    candidates: Vec<T>,
+    embedding: impl Fn(&T) -> &[f32],
+    count: usize,
+    diversity: f32,
+) -> Vec<T> {
+    let relevance = candidates
+        .iter()
+        .map(|x| cosine_similarity(query, embedding(x)))
+        .collect::<Vec<_>>();
+
+    let mut remaining = (0..candidates.len()).collect::<Vec<_>>();
+    let mut picked: Vec<usize> = vec![];
+
+    while picked.len() < count && !remaining.is_empty() {
+        let score = |index: usize| {
+            let redundancy = picked
+                .iter()
+                .map(|x| {
+                    cosine_similarity(embedding(&candidates[index]), embedding(&candidates[*x]))
+                })
+                .fold(0.0, f32::max);
+
+            (1.0 - diversity) * relevance[index] - diversity * redundancy
+        };
+
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, index)| (position, score(*index)))
+            .fold((0, f32::MIN), |best, x| if x.1 > best.1 { x } else { best });
+
+        picked.push(remaining.remove(position));
+    }
+
+    let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    picked
+        .into_iter()
+        .filter_map(|x| candidates[x].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rank(
+        query: &[f32],
+        candidates: &[(&'static str, [f32; 2])],
+        diversity: f32,
+    ) -> Vec<&'static str> {
+        maximal_marginal_relevance(
+            query,
+            candidates.to_vec(),
+            |x| &x.1,
+            candidates.len(),
+            diversity,
+        )
+        .into_iter()
+        .map(|x| x.0)
+        .collect()
+    }
+
+    #[test]
+    fn no_diversity_ranks_by_relevance() {
+        let candidates = [
+            ("far", [0.0, 1.0]),
+            ("near", [1.0, 0.0]),
+            ("between", [1.0, 1.0]),
+        ];
+
+        assert_eq!(
+            rank(&[1.0, 0.0], &candidates, 0.0),
+            ["near", "between", "far"]
+        );
+    }
+
+    #[test]
+    fn full_diversity_ranks_by_novelty() {
+        let candidates = [
+            ("first", [1.0, 0.0]),
+            ("same", [1.0, 0.0]),
+            ("other", [0.0, 1.0]),
+        ];
+
+        assert_eq!(
+            rank(&[1.0, 0.0], &candidates, 1.0),
+            ["first", "other", "same"]
+        );
+    }
+
+    #[test]
+    fn duplicates_give_way_to_other_candidates() {
+        let candidates = [
+            ("copy", [1.0, 0.0]),
+            ("again", [1.0, 0.0]),
+            ("other", [0.0, 1.0]),
+        ];
+
+        assert_eq!(rank(&[0.8, 0.6], &candidates, 0.0)[..2], ["copy", "again"]);
+        assert_eq!(rank(&[0.8, 0.6], &candidates, 0.5)[..2], ["copy", "other"]);
+    }
+}
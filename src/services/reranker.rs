@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Context};
+use fastembed::{RerankInitOptions, TextRerank};
+
+/// The cross-encoder used when none is configured.
+pub const DEFAULT_RERANKER_MODEL: &str = "BAAI/bge-reranker-base";
+
+/// A service that scores documents against a query with a cross-encoder,
+/// which reads both together and so orders them better than comparing their
+/// embeddings, but is too slow to run over more than a handful.
+pub struct RerankerService {
+    model: TextRerank,
+}
+
+impl RerankerService {
+    /// Loads a reranker by its Hugging Face name, e.g.
+    /// `BAAI/bge-reranker-base`.
+    pub fn try_new(model_code: &str) -> anyhow::Result<Self> {
+        let model_name = TextRerank::list_supported_models()
+            .into_iter()
+            .find(|x| x.model_code == model_code)
+            .map(|x| x.model)
+            .ok_or_else(|| anyhow!("Unsupported reranker model `{model_code}`"))?;
+
+        let model = TextRerank::try_new(RerankInitOptions {
+            model_name,
+            show_download_progress: true,
+            ..Default::default()
+        })?;
+
+        Ok(RerankerService { model })
+    }
+
+    /// Returns the indices of the documents, most relevant to the query
+    /// first.
+    pub fn rerank(&self, query: &str, documents: &[String]) -> anyhow::Result<Vec<usize>> {
+        let documents = documents.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        let results = self
+            .model
+            .rerank(query, documents, false, None)
+            .context("Could not rerank documents.")?;
+
+        let mut results = results
+            .into_iter()
+            .map(|x| (x.index, x.score))
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Ok(results.into_iter().map(|(index, _)| index).collect())
+    }
+}